@@ -9,6 +9,7 @@ use serde::Deserialize;
 use watchers::config::defaults;
 use watchers::config::Config;
 use watchers::config::FileConfig;
+use watchers::config::{parse_backends, SinkType};
 
 #[derive(Deserialize, Default)]
 struct AwAuthConfig {
@@ -37,6 +38,8 @@ fn read_aw_server_api_key() -> Option<String> {
 
 pub struct RunnerConfig {
     pub watchers_config: Config,
+    /// Only print the detected backends.
+    pub detect: bool,
     #[cfg(feature = "bundle")]
     pub config_file: PathBuf,
     #[cfg(feature = "bundle")]
@@ -93,9 +96,21 @@ pub fn from_cli() -> anyhow::Result<RunnerConfig> {
             arg!(--"poll-time-window" <SECONDS> "Period between sending heartbeats to the server for window activity")
                 .value_parser(value_parser!(u32))
                 .default_value(defaults::poll_time_window_seconds().to_string()),
-            arg!(--"no-server" "Don't send data to the ActivityWatch server")
+            arg!(--"keep-alive" <SECONDS> "Period between sending heartbeats by the watchers which are notified about changes")
+                .value_parser(value_parser!(u32))
+                .default_value(defaults::keep_alive_seconds().to_string()),
+            arg!(--"idle-backend" <BACKENDS> "Comma-separated idle backends to try in order, \"auto\" for the rest")
+                .value_parser(value_parser!(String)),
+            arg!(--"window-backend" <BACKENDS> "Comma-separated window backends to try in order, \"auto\" for the rest")
+                .value_parser(value_parser!(String)),
+            arg!(--"no-server" "Don't send data to the ActivityWatch server, same as \"--sink none\"")
+                .value_parser(value_parser!(bool))
+                .action(ArgAction::SetTrue),
+            arg!(--"process-info" "Add the process details from /proc to the window events")
                 .value_parser(value_parser!(bool))
                 .action(ArgAction::SetTrue),
+            arg!(--sink <SINK> "Where to report the data: ActivityWatch server, JSON lines file or nowhere")
+                .value_parser(["server", "jsonl", "none"]),
             #[cfg(feature = "bundle")]
             arg!(--"no-tray" "Don't use the bundled tray, run only server and watchers in the background")
                 .value_parser(value_parser!(bool))
@@ -105,6 +120,10 @@ pub fn from_cli() -> anyhow::Result<RunnerConfig> {
                 .help("Verbosity level: -v for warnings, -vv for info, -vvv for debug, -vvvv for trace")
                 .action(ArgAction::Count),
         ])
+        .subcommand(
+            Command::new("detect")
+                .about("Probe all idle and window backends and show which ones would be selected"),
+        )
         .get_matches();
 
     let config = new_with_cli(&matches)?;
@@ -118,12 +137,49 @@ pub fn from_cli() -> anyhow::Result<RunnerConfig> {
     };
     setup_logger(verbosity)?;
 
-    let is_local = ["localhost", "127.0.0.1", "::1"].contains(&config.server.host.as_str());
+    let servers = config
+        .servers
+        .iter()
+        .map(|server| {
+            let api_key = resolve_api_key(&server.host, server.api_key.as_deref());
+            server.get_server(api_key)
+        })
+        .collect();
+    let hostname = config.client.get_hostname();
+
+    Ok(RunnerConfig {
+        watchers_config: Config {
+            servers,
+            idle_timeout: config.client.get_idle_timeout(),
+            poll_time_idle: config.client.get_poll_time_idle(),
+            poll_time_window: config.client.get_poll_time_window(),
+            keep_alive: config.client.get_keep_alive(),
+            sink: config.client.get_sink(),
+            idle_bucket_name: config.client.get_bucket_afk(&hostname),
+            active_window_bucket_name: config.client.get_bucket_window(&hostname),
+            media_players: config.client.get_media_players(),
+            hostname,
+            idle_backends: config.client.idle_backend,
+            window_backends: config.client.window_backend,
+            process_info: config.client.process_info,
+            screen_lock: config.client.screen_lock,
+            mark_locked: config.client.mark_locked,
+            media_idle: config.client.media_idle,
+            idle_timeouts: config.client.idle_timeouts,
+            filters: config.client.filters,
+        },
+        detect: matches.subcommand_matches("detect").is_some(),
+        #[cfg(feature = "bundle")]
+        config_file: config.config_file,
+        #[cfg(feature = "bundle")]
+        no_tray: *matches.get_one("no-tray").unwrap(),
+    })
+}
+
+fn resolve_api_key(host: &str, api_key: Option<&str>) -> Option<String> {
+    let is_local = ["localhost", "127.0.0.1", "::1"].contains(&host);
 
-    let api_key = config
-        .server
-        .api_key
-        .as_deref()
+    api_key
         .map(str::trim)
         .filter(|k| !k.is_empty())
         .map(|k| {
@@ -144,24 +200,7 @@ pub fn from_cli() -> anyhow::Result<RunnerConfig> {
                 warn!("No API key found in awatcher config and host is not local, proceeding unauthenticated");
                 None
             }
-        });
-
-    Ok(RunnerConfig {
-        watchers_config: Config {
-            port: config.server.port,
-            host: config.server.host,
-            api_key,
-            idle_timeout: config.client.get_idle_timeout(),
-            poll_time_idle: config.client.get_poll_time_idle(),
-            poll_time_window: config.client.get_poll_time_window(),
-            filters: config.client.filters,
-            no_server: *matches.get_one("no-server").unwrap(),
-        },
-        #[cfg(feature = "bundle")]
-        config_file: config.config_file,
-        #[cfg(feature = "bundle")]
-        no_tray: *matches.get_one("no-tray").unwrap(),
-    })
+        })
 }
 
 pub fn new_with_cli(matches: &ArgMatches) -> anyhow::Result<FileConfig> {
@@ -190,14 +229,33 @@ fn merge_cli(config: &mut FileConfig, matches: &ArgMatches) {
         matches,
         &mut config.client.poll_time_window_seconds,
     );
+    get_arg_value("keep-alive", matches, &mut config.client.keep_alive_seconds);
     get_arg_value(
         "idle-timeout",
         matches,
         &mut config.client.idle_timeout_seconds,
     );
-    get_arg_value("port", matches, &mut config.server.port);
-    #[cfg(not(feature = "bundle"))]
-    get_arg_value("host", matches, &mut config.server.host);
+    if let Some(backends) = matches.get_one::<String>("idle-backend") {
+        config.client.idle_backend = parse_backends(backends);
+    }
+    if let Some(backends) = matches.get_one::<String>("window-backend") {
+        config.client.window_backend = parse_backends(backends);
+    }
+    if let Some(sink) = matches.get_one::<String>("sink") {
+        config.client.sink = sink.parse().unwrap();
+    }
+    if matches.get_flag("process-info") {
+        config.client.process_info = true;
+    }
+    if matches.get_flag("no-server") {
+        config.client.sink = SinkType::None;
+    }
+    // The CLI overrides only the first of the configured servers
+    if let Some(server) = config.servers.first_mut() {
+        get_arg_value("port", matches, &mut server.port);
+        #[cfg(not(feature = "bundle"))]
+        get_arg_value("host", matches, &mut server.host);
+    }
 }
 
 fn get_arg_value<T>(id: &str, matches: &ArgMatches, config_value: &mut T)
@@ -0,0 +1,67 @@
+use std::env;
+use std::sync::Arc;
+use watchers::config::{Config, Sink};
+use watchers::{probe_backends, Probe, ReportClient, WatcherType};
+
+const ENVIRONMENT: &[&str] = &[
+    "WAYLAND_DISPLAY",
+    "XDG_SESSION_TYPE",
+    "XDG_CURRENT_DESKTOP",
+    "KDE_SESSION_VERSION",
+];
+
+pub async fn run(mut config: Config) -> anyhow::Result<()> {
+    // Probing must not report anything
+    config.sink = Sink::None;
+    let client = Arc::new(
+        ReportClient::new(config)
+            .await
+            .map_err(|e| anyhow::anyhow!("{e}"))?,
+    );
+
+    println!("Environment:");
+    for name in ENVIRONMENT {
+        let value = env::var(name).unwrap_or_else(|_| "<unset>".to_string());
+        println!("  {name}={value}");
+    }
+
+    for watcher_type in [WatcherType::Idle, WatcherType::ActiveWindow] {
+        let probes = probe_backends(&client, &watcher_type).await?;
+        println!();
+        print!("{}", format_table(&watcher_type, &probes));
+    }
+
+    Ok(())
+}
+
+fn format_table(watcher_type: &WatcherType, probes: &[Probe]) -> String {
+    let width = probes
+        .iter()
+        .map(|probe| probe.backend.name.len())
+        .max()
+        .unwrap_or_default()
+        .max("BACKEND".len());
+
+    let mut lines = vec![
+        format!("Backends for {watcher_type}:"),
+        format!("  {:width$}  AVAILABLE  SELECTED  DETAILS", "BACKEND"),
+    ];
+    for probe in probes {
+        let available = if probe.error.is_none() { "yes" } else { "no" };
+        let selected = if probe.is_selected { "*" } else { "" };
+        let details = match &probe.error {
+            Some(error) => format!("{}: {error}", probe.backend.description),
+            None => probe.backend.description.to_string(),
+        };
+        lines.push(format!(
+            "  {:width$}  {available:9}  {selected:8}  {details}",
+            probe.backend.name
+        ));
+    }
+    lines.push(match probes.iter().find(|probe| probe.is_selected) {
+        Some(probe) => format!("Selected: {}", probe.backend.name),
+        None => "Selected: none, the watcher cannot run".to_string(),
+    });
+
+    lines.join("\n") + "\n"
+}
@@ -7,31 +7,82 @@ extern crate log;
 #[cfg(feature = "bundle")]
 mod bundle;
 mod config;
+mod detect;
 
 use std::error::Error;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::signal::unix::{signal, SignalKind};
 #[cfg(feature = "bundle")]
 use tokio::sync::mpsc;
+#[cfg(feature = "media")]
+use watchers::config::MediaIdle;
+use watchers::config::Sink;
+#[cfg(feature = "media")]
+use watchers::run_media_watcher;
+#[cfg(feature = "screen_lock")]
+use watchers::run_screen_lock_watcher;
+#[cfg(feature = "suspend")]
+use watchers::run_suspend_watcher;
 use watchers::{run_first_supported, ReportClient, WatcherType};
 
+// The queued heartbeats are delivered or spooled before exiting
+const SHUTDOWN_FLUSH_TIMEOUT: Duration = Duration::from_secs(3);
+
+// The session watchers are only additions to the idle watcher, their failures don't stop awatcher
+#[cfg(any(feature = "screen_lock", feature = "suspend", feature = "media"))]
+fn spawn_session_watchers(client: &Arc<ReportClient>) {
+    #[cfg(feature = "screen_lock")]
+    if client.config.screen_lock {
+        let client = Arc::clone(client);
+        tokio::spawn(async move {
+            if let Err(e) = run_screen_lock_watcher(client).await {
+                warn!("Screen lock is not watched: {e}");
+            }
+        });
+    }
+    // Without logind, the suspend is still detected by the clock jump
+    #[cfg(feature = "suspend")]
+    {
+        let client = Arc::clone(client);
+        tokio::spawn(async move {
+            if let Err(e) = run_suspend_watcher(client).await {
+                warn!("Suspend is not watched: {e}");
+            }
+        });
+    }
+
+    #[cfg(feature = "media")]
+    if client.config.media_idle != MediaIdle::Off {
+        let client = Arc::clone(client);
+        tokio::spawn(async move {
+            if let Err(e) = run_media_watcher(client).await {
+                warn!("Media is not watched: {e}");
+            }
+        });
+    }
+}
+
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> anyhow::Result<(), Box<dyn Error>> {
     let config = config::from_cli()?;
+    if config.detect {
+        return Ok(detect::run(config.watchers_config).await?);
+    }
     #[cfg(feature = "bundle")]
     let no_tray = config.no_tray;
     #[cfg(feature = "bundle")]
     let config_file = config.config_file;
     let config = config.watchers_config;
 
-    if config.no_server {
-        warn!(
-            "Not sending to server {}:{}",
-            config.client_host(),
-            config.port
-        );
-    } else {
-        info!("Sending to server {}:{}", config.client_host(), config.port);
+    match &config.sink {
+        Sink::Servers => {
+            for server in &config.servers {
+                info!("Sending to server {}:{}", server.client_host(), server.port);
+            }
+        }
+        Sink::Jsonl(path) => info!("Writing to {}", path.display()),
+        Sink::None => warn!("Not sending to any server"),
     }
     info!(
         "Idle timeout: {} seconds",
@@ -45,12 +96,16 @@ async fn main() -> anyhow::Result<(), Box<dyn Error>> {
         "Window polling period: {} seconds",
         config.poll_time_window.num_seconds()
     );
+    info!(
+        "Keep-alive period: {} seconds",
+        config.keep_alive.num_seconds()
+    );
     #[cfg(feature = "bundle")]
     let (shutdown_send, mut shutdown_recv) = mpsc::unbounded_channel();
     #[cfg(feature = "bundle")]
     let bundle_handle = tokio::spawn(bundle::run(
-        config.host.clone(),
-        config.port,
+        config.servers[0].host.clone(),
+        config.servers[0].port,
         config_file,
         no_tray,
         shutdown_send,
@@ -58,6 +113,9 @@ async fn main() -> anyhow::Result<(), Box<dyn Error>> {
 
     let client = Arc::new(ReportClient::new(config).await?);
 
+    #[cfg(any(feature = "screen_lock", feature = "suspend", feature = "media"))]
+    spawn_session_watchers(&client);
+
     let idle_future = run_first_supported(Arc::clone(&client), &WatcherType::Idle);
     let active_window_future = run_first_supported(Arc::clone(&client), &WatcherType::ActiveWindow);
     let sigterm = async {
@@ -70,24 +128,27 @@ async fn main() -> anyhow::Result<(), Box<dyn Error>> {
     };
 
     #[cfg(not(feature = "bundle"))]
-    {
+    let result: Result<(), Box<dyn Error>> = {
         tokio::select!(
-            _ = tokio::spawn(idle_future) => Ok(()),
-            _ = tokio::spawn(active_window_future) => Ok(()),
+            result = tokio::spawn(idle_future) => Ok(result??),
+            result = tokio::spawn(active_window_future) => Ok(result??),
             () = sigterm => Ok(()),
             () = sigint => Ok(()),
         )
-    }
+    };
 
     #[cfg(feature = "bundle")]
-    {
+    let result: Result<(), Box<dyn Error>> = {
         tokio::select!(
             _ = bundle_handle => Ok(()),
-            _ = tokio::spawn(idle_future) => Ok(()),
-            _ = tokio::spawn(active_window_future) => Ok(()),
+            result = tokio::spawn(idle_future) => Ok(result??),
+            result = tokio::spawn(active_window_future) => Ok(result??),
             () = sigterm => Ok(()),
             () = sigint => Ok(()),
             _ = shutdown_recv.recv() => Ok(()),
         )
-    }
+    };
+
+    client.flush(SHUTDOWN_FLUSH_TIMEOUT).await?;
+    result
 }
@@ -0,0 +1,92 @@
+/*
+ * The wall clock stamps the events, the monotonic clock stops in suspend and detects it by the jumps.
+ * The simulated clock advances explicitly and to the deadlines of the finished sleeps,
+ * which take no time in the tests with the paused tokio time, so they run hours in milliseconds.
+ */
+use async_trait::async_trait;
+use chrono::{DateTime, TimeDelta, Utc};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+#[async_trait]
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+
+    /// Time since an arbitrary start which doesn't advance in suspend.
+    fn monotonic(&self) -> Duration;
+
+    async fn sleep(&self, duration: Duration);
+}
+
+pub struct SystemClock {
+    start: Instant,
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        Self {
+            start: Instant::now(),
+        }
+    }
+}
+
+#[async_trait]
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+
+    fn monotonic(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    async fn sleep(&self, duration: Duration) {
+        tokio::time::sleep(duration).await;
+    }
+}
+
+pub struct SimulatedClock {
+    time: Mutex<(DateTime<Utc>, Duration)>,
+}
+
+impl SimulatedClock {
+    pub fn new(now: DateTime<Utc>) -> Self {
+        Self {
+            time: Mutex::new((now, Duration::ZERO)),
+        }
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        let mut time = self.time.lock().unwrap();
+        time.0 += TimeDelta::from_std(duration).unwrap();
+        time.1 += duration;
+    }
+
+    /// Advances only the wall clock like a suspend.
+    pub fn suspend(&self, duration: Duration) {
+        self.time.lock().unwrap().0 += TimeDelta::from_std(duration).unwrap();
+    }
+}
+
+#[async_trait]
+impl Clock for SimulatedClock {
+    fn now(&self) -> DateTime<Utc> {
+        self.time.lock().unwrap().0
+    }
+
+    fn monotonic(&self) -> Duration {
+        self.time.lock().unwrap().1
+    }
+
+    async fn sleep(&self, duration: Duration) {
+        let deadline = self.monotonic() + duration;
+        tokio::time::sleep(duration).await;
+
+        // Concurrent sleeps don't add up, the time is only moved to the latest deadline
+        let mut time = self.time.lock().unwrap();
+        if let Some(elapsed) = deadline.checked_sub(time.1) {
+            time.0 += TimeDelta::from_std(elapsed).unwrap();
+            time.1 = deadline;
+        }
+    }
+}
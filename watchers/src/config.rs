@@ -1,25 +1,77 @@
 pub mod defaults;
 mod file_config;
 mod filters;
+mod idle_timeouts;
+mod media;
 
-use std::{net::Ipv4Addr, str::FromStr};
+use std::{
+    collections::HashMap, net::Ipv4Addr, path::PathBuf, str::FromStr, time::Duration as StdDuration,
+};
 
 use self::filters::Filter;
 use chrono::Duration;
-pub use file_config::FileConfig;
+pub use file_config::{parse_backends, FileConfig, SinkType};
 pub use filters::FilterResult;
+pub use idle_timeouts::IdleTimeout;
+pub use media::{MediaIdle, MediaPlayers};
 
 pub struct Config {
-    pub port: u16,
-    pub host: String,
-    pub api_key: Option<String>,
+    pub servers: Vec<Server>,
     pub idle_timeout: Duration,
+    /// Overrides of the idle timeout for the focused window, the first matching one is taken.
+    pub idle_timeouts: Vec<IdleTimeout>,
     pub poll_time_idle: Duration,
     pub poll_time_window: Duration,
-    pub no_server: bool,
+    /// Heartbeat period of the watchers which are notified about the changes.
+    pub keep_alive: Duration,
+    /// Names of the backends to try in order, "auto" stands for the rest in the default order.
+    pub idle_backends: Vec<String>,
+    pub window_backends: Vec<String>,
+    pub sink: Sink,
+    /// Hostname of the buckets.
+    pub hostname: String,
+    pub idle_bucket_name: String,
+    pub active_window_bucket_name: String,
+    /// Add the process details from /proc to the windows with a known PID.
+    pub process_info: bool,
+    /// Report the locked screen as idle since the lock.
+    pub screen_lock: bool,
+    /// Add "locked" to the idle events while the screen is locked.
+    pub mark_locked: bool,
+    /// Idle reporting while media plays or idle is inhibited.
+    pub media_idle: MediaIdle,
+    pub media_players: MediaPlayers,
     pub filters: Vec<Filter>,
 }
 
+/// ActivityWatch server to report to, every server has its own buckets, spool and retries.
+pub struct Server {
+    pub port: u16,
+    pub host: String,
+    pub api_key: Option<String>,
+    pub spool_file: Option<PathBuf>,
+    pub retry: RetryPolicy,
+}
+
+pub enum Sink {
+    /// All configured ActivityWatch servers.
+    Servers,
+    /// JSON lines file with buckets and heartbeats.
+    Jsonl(PathBuf),
+    /// The data is not reported anywhere.
+    None,
+}
+
+#[derive(Clone)]
+pub struct RetryPolicy {
+    /// Retries after the first request, 3 means up to 4 requests in total.
+    pub attempts: u32,
+    pub initial_backoff: StdDuration,
+    pub max_backoff: StdDuration,
+    /// Fraction of the backoff to randomly add or subtract.
+    pub jitter: f64,
+}
+
 fn normalize_server_host(server_host: &str) -> String {
     let is_zero_first_octet = match Ipv4Addr::from_str(server_host) {
         Ok(ip) => ip.octets()[0] == 0,
@@ -33,9 +85,14 @@ fn normalize_server_host(server_host: &str) -> String {
 }
 
 impl Config {
-    pub fn match_window_data(&self, app_id: &str, title: &str) -> FilterResult {
+    pub fn match_window_data(
+        &self,
+        app_id: &str,
+        title: &str,
+        extra: &HashMap<String, String>,
+    ) -> FilterResult {
         for filter in &self.filters {
-            let result = filter.apply(app_id, title);
+            let result = filter.apply(app_id, title, extra);
             if matches!(result, FilterResult::Match | FilterResult::Replace(_)) {
                 return result;
             }
@@ -44,6 +101,23 @@ impl Config {
         FilterResult::Skip
     }
 
+    pub fn window_idle_timeout(&self, app_id: &str, title: &str) -> Duration {
+        self.idle_timeouts
+            .iter()
+            .find(|idle_timeout| idle_timeout.is_match(app_id, title))
+            .map_or(self.idle_timeout, IdleTimeout::timeout)
+    }
+
+    /// The idle notifications are requested with it to let the longer timeouts be counted from them.
+    pub fn shortest_idle_timeout(&self) -> Duration {
+        self.idle_timeouts
+            .iter()
+            .map(IdleTimeout::timeout)
+            .fold(self.idle_timeout, Duration::min)
+    }
+}
+
+impl Server {
     pub fn client_host(&self) -> String {
         normalize_server_host(&self.host)
     }
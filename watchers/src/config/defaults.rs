@@ -1,3 +1,5 @@
+use std::path::PathBuf;
+
 pub fn idle_timeout_seconds() -> u32 {
     180
 }
@@ -7,9 +9,62 @@ pub fn poll_time_idle_seconds() -> u32 {
 pub fn poll_time_window_seconds() -> u32 {
     1
 }
+pub fn keep_alive_seconds() -> u32 {
+    30
+}
+pub fn backend() -> Vec<String> {
+    vec!["auto".to_string()]
+}
 pub fn port() -> u16 {
     5600
 }
 pub fn host() -> String {
     "127.0.0.1".to_string()
 }
+pub fn offline_spool() -> bool {
+    true
+}
+pub fn spool_file(host: &str, port: u16) -> Option<PathBuf> {
+    let host: String = host
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+
+    dirs::state_dir().or_else(dirs::data_local_dir).map(|dir| {
+        dir.join("awatcher")
+            .join(format!("spool_{host}_{port}.jsonl"))
+    })
+}
+pub fn bucket_afk() -> String {
+    "aw-watcher-afk_{hostname}".to_string()
+}
+pub fn bucket_window() -> String {
+    "aw-watcher-window_{hostname}".to_string()
+}
+pub fn process_info() -> bool {
+    false
+}
+pub fn screen_lock() -> bool {
+    true
+}
+pub fn mark_locked() -> bool {
+    false
+}
+pub fn retry_attempts() -> u32 {
+    3
+}
+pub fn retry_backoff_ms() -> u64 {
+    1000
+}
+pub fn retry_max_backoff_ms() -> u64 {
+    30000
+}
+pub fn retry_jitter() -> f64 {
+    0.2
+}
+pub fn jsonl_file() -> PathBuf {
+    dirs::data_local_dir()
+        .unwrap_or_default()
+        .join("awatcher")
+        .join("events.jsonl")
+}
@@ -1,25 +1,75 @@
 use anyhow::{anyhow, Context};
 use chrono::TimeDelta;
-use serde::Deserialize;
+use regex::Regex;
+use serde::{Deserialize, Deserializer};
 use serde_default::DefaultFromSerde;
-use std::{fs, io::ErrorKind, path::PathBuf};
+use std::{fs, io::ErrorKind, path::PathBuf, str::FromStr, time::Duration};
 
-use crate::config::defaults;
+use crate::config::{defaults, RetryPolicy, Server, Sink};
 
 use super::filters::Filter;
+use super::idle_timeouts::IdleTimeout;
+use super::media::{strings_to_regex_list, MediaIdle, MediaPlayers};
 
 pub fn default_config() -> String {
     format!(
         r#"# The commented values are the defaults on the file creation
+# Use [[server]] instead of [server] for every server to report to several servers at once.
 [server]
 # port = {}
 # host = "{}"
 # api-key = "{}"
+# Heartbeats are kept in a file while the server is unavailable and sent when it's back
+# offline-spool = {}
+# spool-file = "{}"
+# Connection failures, timeouts and server errors are retried with an exponential backoff,
+# retry-attempts is the number of retries after the first request
+# retry-attempts = {}
+# retry-backoff-ms = {}
+# retry-max-backoff-ms = {}
+# retry-jitter = {}
 
 [awatcher]
 # idle-timeout-seconds={}
 # poll-time-idle-seconds={}
 # poll-time-window-seconds={}
+# Period between heartbeats when the watcher is notified about the changes instead of polling
+# keep-alive-seconds={}
+# Backends to try in order, a name, a list like ["x11", "auto"] or "auto" for the automatic selection.
+# Run "awatcher detect" to see the available backends.
+# idle-backend = "auto"
+# window-backend = "auto"
+# Where to report: "server", "jsonl" to append to jsonl-file or "none"
+# sink = "server"
+# jsonl-file = "{}"
+# The hostname of the buckets, the system hostname if not set
+# hostname = ""
+# Bucket names, "{{hostname}}" is replaced by the hostname
+# bucket-afk = "{}"
+# bucket-window = "{}"
+# Add the executable, working directory and parent processes of the window from /proc if its PID is known,
+# and of the foreground process in terminals, the paths are sent to every server
+# process-info = {}
+# Report the screen lock by logind or the screen saver as idle right away, since the lock
+# screen-lock = {}
+# Add "locked": true to the data of the idle events while the screen is locked
+# mark-locked = {}
+# While an MPRIS player plays (or idle is inhibited with media-inhibitors): "off" to report idle as usual,
+# "suppress" to report it as active, "passive" to report it with the "passive" status
+# media-idle = "off"
+# MPRIS players to take into account by the desktop entry or the bus name, all if the list is empty
+# media-allow = []
+# media-deny = []
+# Count the idle inhibitors of GNOME Session Manager or PowerManagement as playing media,
+# their applications are unknown, so media-allow and media-deny don't apply to them
+# media-inhibitors = false
+
+# Idle timeouts of the focused windows instead of idle-timeout-seconds, the first matching one is taken.
+# There should be at least 1 match field, the matches are the same as in the filters below.
+# [[awatcher.idle-timeouts]]
+# match-app-id = "firefox"
+# match-title = ".*Docs.*"
+# seconds = 600
 
 # Add as many filters as needed. The first matching filter stops the replacement.
 # There should be at least 1 match field, and at least 1 replace field.
@@ -42,13 +92,35 @@ pub fn default_config() -> String {
 # match-app-id = "code"
 # match-title = "● (.*)"
 # replace-title = "$1"
+
+# Extra data of the watcher is matched by its name, a missing value doesn't match.
+# E.g. X11 reports "wm_instance", "pid", "role" and "window_type", this rule hides titles of Chrome apps.
+# [[awatcher.filters]]
+# match-app-id = "Google-chrome"
+# match-extra = {{ wm_instance = "crx_.*" }}
+# replace-title = "Chrome app"
 "#,
         defaults::port(),
         defaults::host(),
         "",
+        defaults::offline_spool(),
+        defaults::spool_file(&defaults::host(), defaults::port())
+            .unwrap_or_default()
+            .display(),
+        defaults::retry_attempts(),
+        defaults::retry_backoff_ms(),
+        defaults::retry_max_backoff_ms(),
+        defaults::retry_jitter(),
         defaults::idle_timeout_seconds(),
         defaults::poll_time_idle_seconds(),
         defaults::poll_time_window_seconds(),
+        defaults::keep_alive_seconds(),
+        defaults::jsonl_file().display(),
+        defaults::bucket_afk(),
+        defaults::bucket_window(),
+        defaults::process_info(),
+        defaults::screen_lock(),
+        defaults::mark_locked(),
     )
 }
 
@@ -60,6 +132,74 @@ pub struct ServerConfig {
     pub host: String,
     #[serde(default, rename = "api-key", alias = "api_key")]
     pub api_key: Option<String>,
+    #[serde(default = "defaults::offline_spool", rename = "offline-spool")]
+    pub offline_spool: bool,
+    #[serde(default, rename = "spool-file")]
+    pub spool_file: Option<PathBuf>,
+    #[serde(default = "defaults::retry_attempts", rename = "retry-attempts")]
+    pub retry_attempts: u32,
+    #[serde(default = "defaults::retry_backoff_ms", rename = "retry-backoff-ms")]
+    pub retry_backoff_ms: u64,
+    #[serde(
+        default = "defaults::retry_max_backoff_ms",
+        rename = "retry-max-backoff-ms"
+    )]
+    pub retry_max_backoff_ms: u64,
+    #[serde(default = "defaults::retry_jitter", rename = "retry-jitter")]
+    pub retry_jitter: f64,
+}
+
+impl ServerConfig {
+    pub fn get_spool_file(&self) -> Option<PathBuf> {
+        if self.offline_spool {
+            self.spool_file
+                .clone()
+                .or_else(|| defaults::spool_file(&self.host, self.port))
+        } else {
+            None
+        }
+    }
+
+    pub fn get_server(&self, api_key: Option<String>) -> Server {
+        Server {
+            port: self.port,
+            host: self.host.clone(),
+            api_key,
+            spool_file: self.get_spool_file(),
+            retry: self.get_retry_policy(),
+        }
+    }
+
+    pub fn get_retry_policy(&self) -> RetryPolicy {
+        RetryPolicy {
+            attempts: self.retry_attempts,
+            initial_backoff: Duration::from_millis(self.retry_backoff_ms),
+            max_backoff: Duration::from_millis(self.retry_max_backoff_ms),
+            jitter: self.retry_jitter.clamp(0.0, 1.0),
+        }
+    }
+}
+
+#[derive(Deserialize, Default, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SinkType {
+    #[default]
+    Server,
+    Jsonl,
+    None,
+}
+
+impl FromStr for SinkType {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "server" => Ok(Self::Server),
+            "jsonl" => Ok(Self::Jsonl),
+            "none" => Ok(Self::None),
+            _ => Err(anyhow!("Unknown sink \"{s}\"")),
+        }
+    }
 }
 
 #[derive(Deserialize, DefaultFromSerde)]
@@ -71,6 +211,38 @@ pub struct ClientConfig {
     pub poll_time_idle_seconds: u32,
     #[serde(default = "defaults::poll_time_window_seconds")]
     pub poll_time_window_seconds: u32,
+    #[serde(default = "defaults::keep_alive_seconds")]
+    pub keep_alive_seconds: u32,
+    #[serde(default = "defaults::backend", deserialize_with = "string_or_list")]
+    pub idle_backend: Vec<String>,
+    #[serde(default = "defaults::backend", deserialize_with = "string_or_list")]
+    pub window_backend: Vec<String>,
+    #[serde(default)]
+    pub sink: SinkType,
+    #[serde(default)]
+    pub jsonl_file: Option<PathBuf>,
+    #[serde(default)]
+    pub hostname: Option<String>,
+    #[serde(default = "defaults::bucket_afk")]
+    pub bucket_afk: String,
+    #[serde(default = "defaults::bucket_window")]
+    pub bucket_window: String,
+    #[serde(default = "defaults::process_info")]
+    pub process_info: bool,
+    #[serde(default = "defaults::screen_lock")]
+    pub screen_lock: bool,
+    #[serde(default = "defaults::mark_locked")]
+    pub mark_locked: bool,
+    #[serde(default)]
+    pub media_idle: MediaIdle,
+    #[serde(default, deserialize_with = "strings_to_regex_list")]
+    pub media_allow: Vec<Regex>,
+    #[serde(default, deserialize_with = "strings_to_regex_list")]
+    pub media_deny: Vec<Regex>,
+    #[serde(default)]
+    pub media_inhibitors: bool,
+    #[serde(default)]
+    pub idle_timeouts: Vec<IdleTimeout>,
     #[serde(default)]
     pub filters: Vec<Filter>,
 }
@@ -87,12 +259,106 @@ impl ClientConfig {
     pub fn get_poll_time_window(&self) -> TimeDelta {
         TimeDelta::seconds(self.poll_time_window_seconds.into())
     }
+
+    pub fn get_keep_alive(&self) -> TimeDelta {
+        TimeDelta::seconds(self.keep_alive_seconds.into())
+    }
+
+    pub fn get_sink(&self) -> Sink {
+        match self.sink {
+            SinkType::Server => Sink::Servers,
+            SinkType::Jsonl => {
+                Sink::Jsonl(self.jsonl_file.clone().unwrap_or_else(defaults::jsonl_file))
+            }
+            SinkType::None => Sink::None,
+        }
+    }
+
+    pub fn get_hostname(&self) -> String {
+        self.hostname
+            .as_deref()
+            .map(str::trim)
+            .filter(|hostname| !hostname.is_empty())
+            .map_or_else(
+                || gethostname::gethostname().to_string_lossy().into_owned(),
+                ToString::to_string,
+            )
+    }
+
+    pub fn get_bucket_afk(&self, hostname: &str) -> String {
+        self.bucket_afk.replace("{hostname}", hostname)
+    }
+
+    pub fn get_bucket_window(&self, hostname: &str) -> String {
+        self.bucket_window.replace("{hostname}", hostname)
+    }
+
+    pub fn get_media_players(&self) -> MediaPlayers {
+        MediaPlayers::new(
+            self.media_allow.clone(),
+            self.media_deny.clone(),
+            self.media_inhibitors,
+        )
+    }
+}
+
+/// Splits a comma-separated list of backend names.
+pub fn parse_backends(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(ToString::to_string)
+        .collect()
+}
+
+fn string_or_list<'de, D>(d: D) -> Result<Vec<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum StringOrList {
+        String(String),
+        List(Vec<String>),
+    }
+
+    Ok(match StringOrList::deserialize(d)? {
+        StringOrList::String(value) => parse_backends(&value),
+        StringOrList::List(names) => names,
+    })
 }
 
-#[derive(Deserialize, Default)]
+fn default_servers() -> Vec<ServerConfig> {
+    vec![ServerConfig::default()]
+}
+
+// Both a single [server] table and an array of [[server]] tables are accepted.
+fn one_or_many<'de, D>(d: D) -> Result<Vec<ServerConfig>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(ServerConfig),
+        Many(Vec<ServerConfig>),
+    }
+
+    Ok(match OneOrMany::deserialize(d)? {
+        OneOrMany::One(server) => vec![server],
+        OneOrMany::Many(servers) => servers,
+    })
+}
+
+#[derive(Deserialize)]
 pub struct FileConfig {
-    #[serde(default)]
-    pub server: ServerConfig,
+    #[serde(
+        default = "default_servers",
+        rename = "server",
+        deserialize_with = "one_or_many"
+    )]
+    pub servers: Vec<ServerConfig>,
     #[serde(default)]
     #[serde(rename = "awatcher")]
     pub client: ClientConfig,
@@ -100,6 +366,16 @@ pub struct FileConfig {
     pub config_file: PathBuf,
 }
 
+impl Default for FileConfig {
+    fn default() -> Self {
+        Self {
+            servers: default_servers(),
+            client: ClientConfig::default(),
+            config_file: PathBuf::default(),
+        }
+    }
+}
+
 impl FileConfig {
     pub fn new(config_override: Option<PathBuf>) -> anyhow::Result<Self> {
         let is_config_overridden = config_override.is_some();
@@ -143,6 +419,9 @@ impl FileConfig {
 
             Self::default()
         };
+        if config.servers.is_empty() {
+            anyhow::bail!("At least one server must be configured");
+        }
         config.config_file = config_path;
 
         Ok(config)
@@ -155,6 +434,7 @@ mod tests {
 
     use super::*;
     use rstest::rstest;
+    use std::collections::HashMap;
     use std::io::Write;
     use tempfile::NamedTempFile;
 
@@ -168,11 +448,35 @@ mod tests {
 port = 1234
 host = "http://address.com"
 api-key = "test-api-key"
+spool-file = "/tmp/spool.jsonl"
+retry-attempts = 5
+retry-backoff-ms = 200
+retry-max-backoff-ms = 5000
+retry-jitter = 0.5
 
 [awatcher]
 idle-timeout-seconds=14
 poll-time-idle-seconds=13
 poll-time-window-seconds=12
+keep-alive-seconds=11
+idle-backend = "ext-idle-notify"
+window-backend = ["kwin", "auto"]
+sink = "jsonl"
+jsonl-file = "/tmp/events.jsonl"
+hostname = "workstation"
+bucket-afk = "afk_{{hostname}}_seat1"
+bucket-window = "window-seat1"
+process-info = true
+screen-lock = false
+mark-locked = true
+media-idle = "passive"
+media-allow = ["vlc", "firefox.*"]
+media-deny = ["firefox.private"]
+media-inhibitors = true
+
+[[awatcher.idle-timeouts]]
+match-app-id = "okular"
+seconds = 900
 
 # Add as many filters as needed.
 # There should be at least 1 match field, and at least 1 replace field.
@@ -191,23 +495,60 @@ replace-title = "Title"
 
         let config = FileConfig::new(Some(file.path().to_path_buf())).unwrap();
 
-        assert_eq!(1234, config.server.port);
-        assert_eq!("http://address.com", config.server.host);
-        assert_eq!(Some("test-api-key".to_string()), config.server.api_key);
+        assert_eq!(1234, config.servers[0].port);
+        assert_eq!("http://address.com", config.servers[0].host);
+        assert_eq!(Some("test-api-key".to_string()), config.servers[0].api_key);
+        assert_eq!(
+            Some(PathBuf::from("/tmp/spool.jsonl")),
+            config.servers[0].get_spool_file()
+        );
+        let retry = config.servers[0].get_retry_policy();
+        assert_eq!(5, retry.attempts);
+        assert_eq!(Duration::from_millis(200), retry.initial_backoff);
+        assert_eq!(Duration::from_secs(5), retry.max_backoff);
+        assert!((retry.jitter - 0.5).abs() < f64::EPSILON);
 
         assert_eq!(14, config.client.idle_timeout_seconds);
         assert_eq!(13, config.client.poll_time_idle_seconds);
         assert_eq!(12, config.client.poll_time_window_seconds);
+        assert_eq!(11, config.client.keep_alive_seconds);
+        assert_eq!(vec!["ext-idle-notify"], config.client.idle_backend);
+        assert_eq!(vec!["kwin", "auto"], config.client.window_backend);
+        assert!(matches!(
+            config.client.get_sink(),
+            Sink::Jsonl(path) if path.to_str() == Some("/tmp/events.jsonl")
+        ));
+        let hostname = config.client.get_hostname();
+        assert_eq!("workstation", hostname);
+        assert_eq!(
+            "afk_workstation_seat1",
+            config.client.get_bucket_afk(&hostname)
+        );
+        assert_eq!("window-seat1", config.client.get_bucket_window(&hostname));
+        assert!(config.client.process_info);
+        assert!(!config.client.screen_lock);
+        assert!(config.client.mark_locked);
+        assert_eq!(MediaIdle::Passive, config.client.media_idle);
+        assert_eq!(2, config.client.media_allow.len());
+        assert!(config.client.media_allow[1].is_match("firefox.instance12"));
+        assert!(!config.client.media_deny[0].is_match("firefox.private2"));
+        assert!(config.client.media_inhibitors);
+        assert_eq!(1, config.client.idle_timeouts.len());
+        assert!(config.client.idle_timeouts[0].is_match("okular", "Manual.pdf"));
+        assert_eq!(
+            TimeDelta::seconds(900),
+            config.client.idle_timeouts[0].timeout()
+        );
 
         assert_eq!(2, config.client.filters.len());
 
-        let replacement1 = config.client.filters[0].apply("firefox", "any");
+        let replacement1 = config.client.filters[0].apply("firefox", "any", &HashMap::new());
         assert!(matches!(replacement1, FilterResult::Replace(ref r) if
             r.replace_app_id.is_none() &&
             r.replace_title == Some("Unknown".to_string())
         ));
 
-        let replacement2 = config.client.filters[1].apply("code", "title");
+        let replacement2 = config.client.filters[1].apply("code", "title", &HashMap::new());
         assert!(matches!(replacement2, FilterResult::Replace(ref r) if
             r.replace_app_id == Some("VSCode".to_string()) &&
             r.replace_title == Some("Title".to_string())
@@ -222,15 +563,21 @@ replace-title = "Title"
             r#"
 [[awatcher.filters]]
 match-app-id = "firefox"
+
+[[awatcher.filters]]
+match-extra = {{ wm_instance = "crx_.*" }}
         "#
         )
         .unwrap();
 
         let config = FileConfig::new(Some(file.path().to_path_buf())).unwrap();
 
-        assert_eq!(1, config.client.filters.len());
-        let replacement1 = config.client.filters[0].apply("firefox", "any");
+        assert_eq!(2, config.client.filters.len());
+        let replacement1 = config.client.filters[0].apply("firefox", "any", &HashMap::new());
         assert!(matches!(replacement1, FilterResult::Match));
+        let extra = HashMap::from([("wm_instance".to_string(), "crx_abc".to_string())]);
+        let replacement2 = config.client.filters[1].apply("chrome", "any", &extra);
+        assert!(matches!(replacement2, FilterResult::Match));
     }
 
     #[rstest]
@@ -240,9 +587,18 @@ match-app-id = "firefox"
 
         let config = FileConfig::new(Some(file.path().to_path_buf())).unwrap();
 
-        assert_eq!(defaults::port(), config.server.port);
-        assert_eq!(defaults::host(), config.server.host);
-        assert_eq!(None, config.server.api_key);
+        assert_eq!(defaults::port(), config.servers[0].port);
+        assert_eq!(defaults::host(), config.servers[0].host);
+        assert_eq!(None, config.servers[0].api_key);
+        assert_eq!(
+            defaults::spool_file(&defaults::host(), defaults::port()),
+            config.servers[0].get_spool_file()
+        );
+        assert_eq!(defaults::retry_attempts(), config.servers[0].retry_attempts);
+        assert_eq!(
+            defaults::retry_backoff_ms(),
+            config.servers[0].retry_backoff_ms
+        );
 
         assert_eq!(
             defaults::idle_timeout_seconds(),
@@ -256,10 +612,61 @@ match-app-id = "firefox"
             defaults::poll_time_window_seconds(),
             config.client.poll_time_window_seconds
         );
+        assert_eq!(
+            defaults::keep_alive_seconds(),
+            config.client.keep_alive_seconds
+        );
+        assert_eq!(defaults::backend(), config.client.idle_backend);
+        assert_eq!(defaults::backend(), config.client.window_backend);
 
+        assert_eq!(SinkType::Server, config.client.sink);
+        assert_eq!(None, config.client.hostname);
+        assert_eq!("aw-watcher-afk_host", config.client.get_bucket_afk("host"));
+        assert_eq!(
+            "aw-watcher-window_host",
+            config.client.get_bucket_window("host")
+        );
+        assert!(!config.client.process_info);
+        assert!(config.client.screen_lock);
+        assert!(!config.client.mark_locked);
+        assert_eq!(MediaIdle::Off, config.client.media_idle);
+        assert!(config.client.media_allow.is_empty());
+        assert!(config.client.media_deny.is_empty());
+        assert!(!config.client.media_inhibitors);
+        assert!(config.client.idle_timeouts.is_empty());
         assert_eq!(0, config.client.filters.len());
     }
 
+    #[rstest]
+    fn multiple_servers() {
+        let mut file = NamedTempFile::new().unwrap();
+        write!(
+            file,
+            r#"
+[[server]]
+port = 5600
+
+[[server]]
+host = "aw.example.com"
+port = 443
+api-key = "team-key"
+offline-spool = false
+        "#
+        )
+        .unwrap();
+
+        let config = FileConfig::new(Some(file.path().to_path_buf())).unwrap();
+
+        assert_eq!(2, config.servers.len());
+        assert_eq!(defaults::host(), config.servers[0].host);
+        assert_eq!(5600, config.servers[0].port);
+        assert!(config.servers[0].get_spool_file().is_some());
+        assert_eq!("aw.example.com", config.servers[1].host);
+        assert_eq!(443, config.servers[1].port);
+        assert_eq!(Some("team-key".to_string()), config.servers[1].api_key);
+        assert_eq!(None, config.servers[1].get_spool_file());
+    }
+
     #[rstest]
     fn wrong_file() {
         let file = PathBuf::new();
@@ -1,6 +1,7 @@
 use regex::Regex;
 use serde::de::Error;
 use serde::{Deserialize, Deserializer};
+use std::collections::HashMap;
 
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "kebab-case")]
@@ -11,11 +12,15 @@ pub struct Filter {
     #[serde(default)]
     #[serde(deserialize_with = "string_to_regex")]
     match_title: Option<Regex>,
+    // Matches the extra data of the backend by the key, e.g. "wm_instance" or "workspace"
+    #[serde(default)]
+    #[serde(deserialize_with = "strings_to_regexes")]
+    match_extra: HashMap<String, Regex>,
     replace_app_id: Option<String>,
     replace_title: Option<String>,
 }
 
-fn string_to_regex<'de, D>(d: D) -> Result<Option<Regex>, D::Error>
+pub(super) fn string_to_regex<'de, D>(d: D) -> Result<Option<Regex>, D::Error>
 where
     D: Deserializer<'de>,
 {
@@ -31,6 +36,19 @@ where
     }
 }
 
+fn strings_to_regexes<'de, D>(d: D) -> Result<HashMap<String, Regex>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    <HashMap<String, String>>::deserialize(d)?
+        .into_iter()
+        .map(|(key, s)| match format!("^{s}$").parse() {
+            Ok(regex) => Ok((key, regex)),
+            Err(err) => Err(D::Error::custom(err)),
+        })
+        .collect()
+}
+
 #[derive(Debug, PartialEq)]
 pub enum FilterResult {
     Replace(Replacement),
@@ -46,10 +64,10 @@ pub struct Replacement {
 
 impl Filter {
     fn is_valid(&self) -> bool {
-        self.match_app_id.is_some() || self.match_title.is_some()
+        self.match_app_id.is_some() || self.match_title.is_some() || !self.match_extra.is_empty()
     }
 
-    fn is_match(&self, app_id: &str, title: &str) -> bool {
+    fn is_match(&self, app_id: &str, title: &str, extra: &HashMap<String, String>) -> bool {
         if let Some(match_app_id) = &self.match_app_id {
             if !match_app_id.is_match(app_id) {
                 return false;
@@ -61,7 +79,10 @@ impl Filter {
             };
         };
 
-        true
+        // A missing value doesn't match
+        self.match_extra
+            .iter()
+            .all(|(key, regex)| extra.get(key).is_some_and(|value| regex.is_match(value)))
     }
 
     fn replace(regex: &Option<Regex>, source: &str, replacement: &str) -> String {
@@ -74,8 +95,13 @@ impl Filter {
         replacement.to_owned()
     }
 
-    pub fn apply(&self, app_id: &str, title: &str) -> FilterResult {
-        if !self.is_valid() || !self.is_match(app_id, title) {
+    pub fn apply(
+        &self,
+        app_id: &str,
+        title: &str,
+        extra: &HashMap<String, String>,
+    ) -> FilterResult {
+        if !self.is_valid() || !self.is_match(app_id, title, extra) {
             return FilterResult::Skip;
         }
         if self.replace_app_id.is_none() && self.replace_title.is_none() {
@@ -168,11 +194,12 @@ mod tests {
         let filter = Filter {
             match_app_id: match_app_id.map(|s| format!("^{s}$").parse().unwrap()),
             match_title: match_title.map(|s| format!("^{s}$").parse().unwrap()),
+            match_extra: HashMap::new(),
             replace_app_id: replace_app_id.map(option_string),
             replace_title: replace_title.map(option_string),
         };
 
-        let replacement = filter.apply(app_id, title);
+        let replacement = filter.apply(app_id, title, &HashMap::new());
         let expect_replacement = match expect_replacement {
             None => FilterResult::Skip,
             Some((None, None)) => FilterResult::Match,
@@ -183,4 +210,37 @@ mod tests {
         };
         assert_eq!(expect_replacement, replacement);
     }
+
+    #[rstest]
+    #[case::match_extra(&[("wm_instance", "crx_.*")], &[("wm_instance", "crx_abc"), ("pid", "1")], true)]
+    #[case::mismatch_extra(&[("wm_instance", "crx_.*")], &[("wm_instance", "chromium")], false)]
+    #[case::missing_extra(&[("wm_instance", "crx_.*")], &[("pid", "1")], false)]
+    #[case::all_extra(&[("role", "browser"), ("pid", "1")], &[("role", "browser"), ("pid", "2")], false)]
+    fn extra_match(
+        #[case] matches: &[(&str, &str)],
+        #[case] extra: &[(&str, &str)],
+        #[case] is_match: bool,
+    ) {
+        let filter = Filter {
+            match_app_id: Some("^chromium$".parse().unwrap()),
+            match_title: None,
+            match_extra: matches
+                .iter()
+                .map(|(key, s)| (key.to_string(), format!("^{s}$").parse().unwrap()))
+                .collect(),
+            replace_app_id: None,
+            replace_title: None,
+        };
+        let extra = extra
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+
+        let expected = if is_match {
+            FilterResult::Match
+        } else {
+            FilterResult::Skip
+        };
+        assert_eq!(expected, filter.apply("chromium", "Title", &extra));
+    }
 }
@@ -0,0 +1,67 @@
+use super::filters::string_to_regex;
+use chrono::TimeDelta;
+use regex::Regex;
+use serde::Deserialize;
+
+/// Idle timeout of the windows matching the app ID and the title, at least 1 of them is required.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct IdleTimeout {
+    #[serde(default)]
+    #[serde(deserialize_with = "string_to_regex")]
+    match_app_id: Option<Regex>,
+    #[serde(default)]
+    #[serde(deserialize_with = "string_to_regex")]
+    match_title: Option<Regex>,
+    seconds: u32,
+}
+
+impl IdleTimeout {
+    pub fn timeout(&self) -> TimeDelta {
+        TimeDelta::seconds(self.seconds.into())
+    }
+
+    pub fn is_match(&self, app_id: &str, title: &str) -> bool {
+        if self.match_app_id.is_none() && self.match_title.is_none() {
+            return false;
+        }
+
+        self.match_app_id
+            .as_ref()
+            .is_none_or(|regex| regex.is_match(app_id))
+            && self
+                .match_title
+                .as_ref()
+                .is_none_or(|regex| regex.is_match(title))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(Some("firefox"), None, true)]
+    #[case(Some("firefox"), Some(".*\\.pdf.*"), false)]
+    #[case(None, Some("Docs.*"), true)]
+    #[case(Some("org\\.kde\\..*"), None, false)]
+    #[case(None, None, false)]
+    fn matches_window(
+        #[case] match_app_id: Option<&str>,
+        #[case] match_title: Option<&str>,
+        #[case] is_match: bool,
+    ) {
+        let idle_timeout = IdleTimeout {
+            match_app_id: match_app_id.map(|s| format!("^{s}$").parse().unwrap()),
+            match_title: match_title.map(|s| format!("^{s}$").parse().unwrap()),
+            seconds: 600,
+        };
+
+        assert_eq!(
+            is_match,
+            idle_timeout.is_match("firefox", "Docs - Mozilla Firefox")
+        );
+        assert_eq!(TimeDelta::seconds(600), idle_timeout.timeout());
+    }
+}
@@ -0,0 +1,79 @@
+use regex::Regex;
+use serde::de::Error;
+use serde::{Deserialize, Deserializer};
+
+/// How the idle time is reported while media plays or idle is inhibited.
+#[derive(Deserialize, Default, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum MediaIdle {
+    /// Reported as idle.
+    #[default]
+    Off,
+    /// Reported as active.
+    Suppress,
+    /// Reported with the "passive" status.
+    Passive,
+}
+
+/// MPRIS players which are taken into account, by their desktop entry or bus name.
+#[derive(Debug, Default)]
+pub struct MediaPlayers {
+    pub allow: Vec<Regex>,
+    pub deny: Vec<Regex>,
+    /// Idle inhibitors count as a player, regardless of the lists.
+    pub inhibitors: bool,
+}
+
+impl MediaPlayers {
+    pub fn new(allow: Vec<Regex>, deny: Vec<Regex>, inhibitors: bool) -> Self {
+        Self {
+            allow,
+            deny,
+            inhibitors,
+        }
+    }
+
+    pub fn is_allowed(&self, player: &str) -> bool {
+        (self.allow.is_empty() || self.allow.iter().any(|regex| regex.is_match(player)))
+            && !self.deny.iter().any(|regex| regex.is_match(player))
+    }
+}
+
+pub(super) fn strings_to_regex_list<'de, D>(d: D) -> Result<Vec<Regex>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    <Vec<String>>::deserialize(d)?
+        .into_iter()
+        .map(|s| format!("^{s}$").parse().map_err(D::Error::custom))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(&[], &[], "vlc", true)]
+    #[case(&["firefox", "vlc"], &[], "vlc", true)]
+    #[case(&["firefox", "vlc"], &[], "spotify", false)]
+    #[case(&[], &["spotify"], "spotify", false)]
+    #[case(&["vlc.*"], &["vlc.instance.*"], "vlc.instance42", false)]
+    fn allows_players(
+        #[case] allow: &[&str],
+        #[case] deny: &[&str],
+        #[case] player: &str,
+        #[case] is_allowed: bool,
+    ) {
+        let regexes = |patterns: &[&str]| {
+            patterns
+                .iter()
+                .map(|pattern| Regex::new(&format!("^{pattern}$")).unwrap())
+                .collect()
+        };
+        let players = MediaPlayers::new(regexes(allow), regexes(deny), false);
+
+        assert_eq!(is_allowed, players.is_allowed(player));
+    }
+}
@@ -1,10 +1,18 @@
 #[macro_use]
 extern crate log;
 
+pub mod clock;
 pub mod config;
 mod report_client;
 mod watchers;
 
 pub use crate::report_client::ReportClient;
+#[cfg(feature = "media")]
+pub use crate::watchers::media::run_media_watcher;
 pub use crate::watchers::run_first_supported;
+#[cfg(feature = "screen_lock")]
+pub use crate::watchers::screen_lock::run_screen_lock_watcher;
+#[cfg(feature = "suspend")]
+pub use crate::watchers::suspend::run_suspend_watcher;
 pub use crate::watchers::WatcherType;
+pub use crate::watchers::{probe_backends, Backend, Probe};
@@ -1,67 +1,184 @@
-use super::config::{Config, FilterResult};
-use crate::watchers::idle::Status;
+mod jsonl_sink;
+mod process;
+mod retry;
+mod server_sink;
+mod sink;
+mod spool;
+#[cfg(test)]
+mod test_server;
+
+use self::jsonl_sink::JsonlSink;
+use self::process::ProcessReader;
+use self::server_sink::ServerSink;
+use self::sink::{EventSink, NoSink};
+use super::clock::{Clock, SystemClock};
+use super::config::{Config, FilterResult, MediaIdle, Sink};
+use crate::watchers::{idle::Status, WatcherType};
 use anyhow::Context;
-use aw_client_rust::{AwClient, Event as AwEvent};
+use aw_client_rust::{Bucket, BucketMetadata, Event as AwEvent};
 use chrono::{DateTime, TimeDelta, Utc};
 use serde_json::{Map, Value};
 use std::collections::HashMap;
 use std::error::Error;
-use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+// A larger difference between the wall and monotonic clocks is a suspend which wasn't reported
+const MAX_CLOCK_JUMP: TimeDelta = TimeDelta::seconds(10);
 
 pub struct ReportClient {
-    pub client: AwClient,
     pub config: Config,
+    pub clock: Arc<dyn Clock>,
     idle_bucket_name: String,
     active_window_bucket_name: String,
+    sinks: Vec<Box<dyn EventSink>>,
+    // Seconds between heartbeats of the running watchers to merge them on the server
+    idle_interval: AtomicU64,
+    window_interval: AtomicU64,
+    last_window_data: Mutex<Option<Map<String, Value>>>,
+    // App ID and title of the focused window before the filters, it chooses the idle timeout
+    focused_window: Mutex<Option<(String, String)>>,
+    processes: Option<ProcessReader>,
+    idle_report: Mutex<IdleReport>,
+}
+
+// The reported idle state, the screen lock and suspend change it apart from the idle watchers
+#[derive(Default)]
+struct IdleReport {
+    is_idle: bool,
+    locked_since: Option<DateTime<Utc>>,
+    suspended_since: Option<DateTime<Utc>>,
+    // Watchers may be unaware of the unlock or resume, the input before them is already reported
+    active_since: Option<DateTime<Utc>>,
+    // Media plays or idle is inhibited, the idle time is reported by config.media_idle since then
+    media_since: Option<DateTime<Utc>>,
+    passive_since: Option<DateTime<Utc>>,
+    // Wall clock and monotonic time of the last idle status
+    last_status: Option<(DateTime<Utc>, Duration)>,
 }
 
 impl ReportClient {
     pub async fn new(config: Config) -> anyhow::Result<Self, Box<dyn Error>> {
-        let client = AwClient::new_with_api_key(
-            &config.client_host(),
-            config.port,
-            "awatcher",
-            config.api_key.clone(),
-        )?;
-
-        let hostname = gethostname::gethostname().into_string().unwrap();
-        let idle_bucket_name = format!("aw-watcher-afk_{hostname}");
-        let active_window_bucket_name = format!("aw-watcher-window_{hostname}");
-        if !config.no_server {
-            Self::create_bucket(&client, &idle_bucket_name, "afkstatus").await?;
-            Self::create_bucket(&client, &active_window_bucket_name, "currentwindow").await?;
-        }
+        Self::with_clock(config, Arc::new(SystemClock::default())).await
+    }
+
+    pub async fn with_clock(
+        config: Config,
+        clock: Arc<dyn Clock>,
+    ) -> anyhow::Result<Self, Box<dyn Error>> {
+        let idle_bucket_name = config.idle_bucket_name.clone();
+        let active_window_bucket_name = config.active_window_bucket_name.clone();
 
-        Ok(Self {
-            client,
+        let sinks: Vec<Box<dyn EventSink>> = match &config.sink {
+            Sink::Servers => config
+                .servers
+                .iter()
+                .map(|server| {
+                    let sink = ServerSink::new(server, Arc::clone(&clock))?;
+                    Ok(Box::new(sink) as Box<dyn EventSink>)
+                })
+                .collect::<anyhow::Result<_>>()?,
+            Sink::Jsonl(path) => vec![Box::new(JsonlSink::new(path)?)],
+            Sink::None => vec![Box::new(NoSink)],
+        };
+
+        let idle_interval = AtomicU64::new(config.poll_time_idle.num_seconds().try_into()?);
+        let window_interval = AtomicU64::new(config.poll_time_window.num_seconds().try_into()?);
+
+        let processes = config.process_info.then(ProcessReader::default);
+
+        let client = Self {
             config,
+            clock,
             idle_bucket_name,
             active_window_bucket_name,
-        })
-    }
-
-    async fn run_with_retries<F, Fut, T, E>(f: F) -> Result<T, E>
-    where
-        F: Fn() -> Fut,
-        Fut: Future<Output = Result<T, E>>,
-        E: std::error::Error + Send + Sync + 'static,
-    {
-        for (attempt, &secs) in [1, 2, 4].iter().enumerate() {
-            match f().await {
-                Ok(val) => return Ok(val),
-                Err(e)
-                    if e.to_string()
-                        .contains("tcp connect error: Connection refused") =>
-                {
-                    warn!("Failed to connect on attempt #{attempt}, retrying: {e}");
+            sinks,
+            idle_interval,
+            window_interval,
+            last_window_data: Mutex::new(None),
+            focused_window: Mutex::new(None),
+            processes,
+            idle_report: Mutex::new(IdleReport::default()),
+        };
+        client
+            .create_bucket(&client.idle_bucket_name, "afkstatus")
+            .await?;
+        client
+            .create_bucket(&client.active_window_bucket_name, "currentwindow")
+            .await?;
 
-                    tokio::time::sleep(tokio::time::Duration::from_secs(secs)).await;
-                }
-                Err(e) => return Err(e),
+        Ok(client)
+    }
+
+    pub fn set_heartbeat_interval(&self, watcher_type: &WatcherType, interval: Duration) {
+        let seconds = match watcher_type {
+            WatcherType::Idle => &self.idle_interval,
+            WatcherType::ActiveWindow => &self.window_interval,
+        };
+        seconds.store(interval.as_secs(), Ordering::Relaxed);
+    }
+
+    async fn create_bucket(&self, bucket_name: &str, bucket_type: &str) -> anyhow::Result<()> {
+        let bucket = Bucket {
+            bid: None,
+            id: bucket_name.to_string(),
+            _type: bucket_type.to_string(),
+            client: "awatcher".to_string(),
+            hostname: self.config.hostname.clone(),
+            created: Some(self.clock.now()),
+            data: Map::new(),
+            metadata: BucketMetadata::default(),
+            events: None,
+            last_updated: None,
+        };
+
+        let mut errors = Vec::new();
+        for sink in &self.sinks {
+            if let Err(e) = sink.create_bucket(&bucket).await {
+                errors.push(e);
+            }
+        }
+        self.collect_errors(errors)
+    }
+
+    async fn heartbeat(
+        &self,
+        bucket_name: &str,
+        event: &AwEvent,
+        pulsetime: f64,
+    ) -> anyhow::Result<()> {
+        let mut errors = Vec::new();
+        for sink in &self.sinks {
+            if let Err(e) = sink.heartbeat(bucket_name, event, pulsetime).await {
+                errors.push(e);
             }
         }
+        self.collect_errors(errors)
+    }
 
-        f().await
+    /// Waits for the sinks to deliver the queued heartbeats, at most for `timeout`.
+    pub async fn flush(&self, timeout: Duration) -> anyhow::Result<()> {
+        let deadline = self.clock.monotonic() + timeout;
+        let mut errors = Vec::new();
+        for sink in &self.sinks {
+            let timeout = deadline.saturating_sub(self.clock.monotonic());
+            if let Err(e) = sink.flush(timeout).await {
+                errors.push(e);
+            }
+        }
+        self.collect_errors(errors)
+    }
+
+    // A failing target is only reported, unless all of them fail.
+    fn collect_errors(&self, mut errors: Vec<anyhow::Error>) -> anyhow::Result<()> {
+        if !errors.is_empty() && errors.len() == self.sinks.len() {
+            return Err(errors.pop().unwrap());
+        }
+        for e in errors {
+            error!("{e:?}");
+        }
+        Ok(())
     }
 
     pub async fn ping(
@@ -69,12 +186,41 @@ impl ReportClient {
         is_idle: bool,
         timestamp: DateTime<Utc>,
         duration: TimeDelta,
+    ) -> anyhow::Result<()> {
+        let status = if is_idle { "afk" } else { "not-afk" };
+        self.send_idle_event(status, false, timestamp, duration)
+            .await
+    }
+
+    async fn ping_locked(
+        &self,
+        timestamp: DateTime<Utc>,
+        duration: TimeDelta,
+    ) -> anyhow::Result<()> {
+        self.send_idle_event("afk", true, timestamp, duration).await
+    }
+
+    async fn ping_passive(
+        &self,
+        timestamp: DateTime<Utc>,
+        duration: TimeDelta,
+    ) -> anyhow::Result<()> {
+        self.send_idle_event("passive", false, timestamp, duration)
+            .await
+    }
+
+    async fn send_idle_event(
+        &self,
+        status: &str,
+        is_locked: bool,
+        timestamp: DateTime<Utc>,
+        duration: TimeDelta,
     ) -> anyhow::Result<()> {
         let mut data = Map::new();
-        data.insert(
-            "status".to_string(),
-            Value::String((if is_idle { "afk" } else { "not-afk" }).to_string()),
-        );
+        data.insert("status".to_string(), Value::String(status.to_string()));
+        if is_locked && self.config.mark_locked {
+            data.insert("locked".to_string(), Value::Bool(true));
+        }
 
         let event = AwEvent {
             id: None,
@@ -83,17 +229,10 @@ impl ReportClient {
             data,
         };
 
-        if self.config.no_server {
-            return Ok(());
-        }
-
-        let pulsetime = (self.config.idle_timeout + self.config.poll_time_idle).num_seconds();
-        let request = || {
-            self.client
-                .heartbeat(&self.idle_bucket_name, &event, pulsetime as f64)
-        };
+        let pulsetime = self.config.idle_timeout.num_seconds() as f64
+            + self.idle_interval.load(Ordering::Relaxed) as f64;
 
-        Self::run_with_retries(request)
+        self.heartbeat(&self.idle_bucket_name, &event, pulsetime)
             .await
             .with_context(|| "Failed to send heartbeat")
     }
@@ -109,50 +248,100 @@ impl ReportClient {
         title: &str,
         extra_data: Option<HashMap<String, String>>,
     ) -> anyhow::Result<()> {
+        *self.focused_window.lock().unwrap() = Some((app_id.to_string(), title.to_string()));
         let mut data = Map::new();
 
-        if let Some((inserted_app_id, inserted_title)) = self.get_filtered_data(app_id, title) {
+        let mut extra_data = extra_data.unwrap_or_default();
+        // Before the filters to let them match the process details
+        if let (Some(processes), Some(pid)) = (&self.processes, process::window_pid(&extra_data)) {
+            processes.enrich(pid, &mut extra_data);
+        }
+        if let Some((inserted_app_id, inserted_title)) =
+            self.get_filtered_data(app_id, title, &extra_data)
+        {
             trace!("Reporting app_id: {inserted_app_id}, title: {inserted_title}");
 
             data.insert("app".to_string(), Value::String(inserted_app_id));
             data.insert("title".to_string(), Value::String(inserted_title));
 
-            if let Some(extra) = extra_data {
-                for (key, value) in extra {
-                    data.insert(key, Value::String(value));
-                }
+            for (key, value) in extra_data {
+                data.insert(key, Value::String(value));
             }
         } else {
-            return Ok(());
+            return self.close_last_window(None, self.clock.now()).await;
         }
 
+        let timestamp = self.clock.now();
+        self.close_last_window(Some(&data), timestamp).await?;
+
         let event = AwEvent {
             id: None,
-            timestamp: Utc::now(),
+            timestamp,
             duration: TimeDelta::zero(),
             data,
         };
 
-        if self.config.no_server {
-            return Ok(());
+        self.heartbeat(
+            &self.active_window_bucket_name,
+            &event,
+            self.window_pulsetime(),
+        )
+        .await
+        .with_context(|| "Failed to send heartbeat for active window")
+    }
+
+    /// The idle timeout of the focused window.
+    pub fn idle_timeout(&self) -> TimeDelta {
+        match &*self.focused_window.lock().unwrap() {
+            Some((app_id, title)) => self.config.window_idle_timeout(app_id, title),
+            None => self.config.idle_timeout,
         }
+    }
+
+    fn window_pulsetime(&self) -> f64 {
+        (self.window_interval.load(Ordering::Relaxed) + 1) as f64
+    }
+
+    // Extends the previous window up to the switch, otherwise it ends on its last heartbeat
+    // which may be a whole keep-alive period before.
+    async fn close_last_window(
+        &self,
+        data: Option<&Map<String, Value>>,
+        timestamp: DateTime<Utc>,
+    ) -> anyhow::Result<()> {
+        let last_data = {
+            let mut last_data = self.last_window_data.lock().unwrap();
+            if last_data.as_ref() == data {
+                return Ok(());
+            }
+            std::mem::replace(&mut *last_data, data.cloned())
+        };
 
-        let interval_margin = self.config.poll_time_window.num_seconds() + 1;
-        let request = || {
-            self.client.heartbeat(
+        if let Some(last_data) = last_data {
+            let event = AwEvent {
+                id: None,
+                timestamp,
+                duration: TimeDelta::zero(),
+                data: last_data,
+            };
+            self.heartbeat(
                 &self.active_window_bucket_name,
                 &event,
-                interval_margin as f64,
+                self.window_pulsetime(),
             )
-        };
-
-        Self::run_with_retries(request)
             .await
-            .with_context(|| "Failed to send heartbeat for active window")
+            .with_context(|| "Failed to close the previous active window")?;
+        }
+        Ok(())
     }
 
-    fn get_filtered_data(&self, app_id: &str, title: &str) -> Option<(String, String)> {
-        let filter_result = self.config.match_window_data(app_id, title);
+    fn get_filtered_data(
+        &self,
+        app_id: &str,
+        title: &str,
+        extra: &HashMap<String, String>,
+    ) -> Option<(String, String)> {
+        let filter_result = self.config.match_window_data(app_id, title, extra);
         match filter_result {
             FilterResult::Replace(replacement) => {
                 let app_id = if let Some(replace_app_id) = replacement.replace_app_id {
@@ -178,29 +367,263 @@ impl ReportClient {
         }
     }
 
-    async fn create_bucket(
-        client: &AwClient,
-        bucket_name: &str,
-        bucket_type: &str,
-    ) -> anyhow::Result<()> {
-        let request = || client.create_bucket_simple(bucket_name, bucket_type);
+    pub async fn handle_idle_status(&self, status: Status) -> anyhow::Result<()> {
+        let now = self.clock.now();
+        if let Some(suspended_at) = self.detect_clock_jump(now) {
+            warn!(
+                "Clock jumped from {}, reporting it as a suspend",
+                suspended_at.format("%Y-%m-%d %H:%M:%S")
+            );
+            self.set_suspended(true, suspended_at).await?;
+            self.set_suspended(false, now).await?;
+        }
 
-        Self::run_with_retries(request)
-            .await
-            .with_context(|| format!("Failed to create bucket {bucket_name}"))
-    }
+        let (locked_since, is_suspended, active_since, media_since) = {
+            let report = self.idle_report.lock().unwrap();
+            (
+                report.locked_since,
+                report.suspended_since.is_some(),
+                report.active_since,
+                report.media_since,
+            )
+        };
+        if is_suspended {
+            trace!("Skipping idle status while suspended");
+            return Ok(());
+        }
+        if let Some(locked_since) = locked_since {
+            trace!(
+                "Reporting as locked since {}",
+                locked_since.format("%Y-%m-%d %H:%M:%S")
+            );
+            return self.ping_locked(locked_since, now - locked_since).await;
+        }
 
-    pub async fn handle_idle_status(&self, status: Status) -> anyhow::Result<()> {
+        // Changes are taken from the reported state, as the lock may have reported them already
         match status {
             Status::Idle {
-                changed,
                 last_input_time,
                 duration,
-            } => self.idle(changed, last_input_time, duration).await,
+                ..
+            } => {
+                let idle_end = last_input_time + duration;
+                let last_input_time =
+                    active_since.map_or(last_input_time, |t| t.max(last_input_time));
+                match (self.config.media_idle, media_since) {
+                    (MediaIdle::Suppress, Some(_)) => {
+                        self.idle_report.lock().unwrap().active_since = Some(now);
+                        let changed = self.set_reported_idle(false);
+                        return self.non_idle(changed, now).await;
+                    }
+                    (MediaIdle::Passive, Some(media_since)) => {
+                        return self.passive(last_input_time, media_since, now).await;
+                    }
+                    _ => self.idle_report.lock().unwrap().passive_since = None,
+                }
+                let changed = !self.set_reported_idle(true);
+                self.idle(changed, last_input_time, idle_end - last_input_time)
+                    .await
+            }
             Status::Active {
-                changed,
-                last_input_time,
-            } => self.non_idle(changed, last_input_time).await,
+                last_input_time, ..
+            } => {
+                let last_input_time =
+                    active_since.map_or(last_input_time, |t| t.max(last_input_time));
+                let changed = self.set_reported_idle(false);
+                if self
+                    .idle_report
+                    .lock()
+                    .unwrap()
+                    .passive_since
+                    .take()
+                    .is_some()
+                {
+                    debug!(
+                        "Reporting as no longer passive at {}",
+                        last_input_time.format("%Y-%m-%d %H:%M:%S")
+                    );
+                    self.ping_passive(
+                        last_input_time - TimeDelta::milliseconds(1),
+                        TimeDelta::zero(),
+                    )
+                    .await?;
+                    return self.ping(false, last_input_time, TimeDelta::zero()).await;
+                }
+                self.non_idle(changed, last_input_time).await
+            }
+        }
+    }
+
+    // The idle time while media plays is a passive event since the input or the playback start
+    async fn passive(
+        &self,
+        last_input_time: DateTime<Utc>,
+        media_since: DateTime<Utc>,
+        now: DateTime<Utc>,
+    ) -> anyhow::Result<()> {
+        let (was_idle, passive_since) = {
+            let mut report = self.idle_report.lock().unwrap();
+            // The idle event after the playback starts since its end
+            report.active_since = Some(now);
+            (
+                std::mem::replace(&mut report.is_idle, true),
+                report.passive_since,
+            )
+        };
+        if let Some(passive_since) = passive_since {
+            trace!(
+                "Reporting as passive since {}",
+                passive_since.format("%Y-%m-%d %H:%M:%S")
+            );
+            return self.ping_passive(passive_since, now - passive_since).await;
+        }
+
+        let passive_since = last_input_time.max(media_since);
+        self.idle_report.lock().unwrap().passive_since = Some(passive_since);
+        debug!(
+            "Reporting as changed to passive since {}",
+            passive_since.format("%Y-%m-%d %H:%M:%S")
+        );
+        if !was_idle {
+            self.ping(false, last_input_time, TimeDelta::zero()).await?;
+        }
+        if passive_since > last_input_time {
+            self.ping(true, last_input_time, passive_since - last_input_time)
+                .await?;
+        }
+        self.ping_passive(
+            passive_since,
+            now - passive_since + TimeDelta::milliseconds(1),
+        )
+        .await
+    }
+
+    /// Keeps the start of the playback or idle inhibition, the changes are reported on the idle status.
+    pub fn set_media_playing(&self, is_playing: bool, now: DateTime<Utc>) {
+        let mut report = self.idle_report.lock().unwrap();
+        if report.media_since.is_some() != is_playing {
+            debug!(
+                "Media {} at {}",
+                if is_playing { "started" } else { "stopped" },
+                now.format("%Y-%m-%d %H:%M:%S")
+            );
+            report.media_since = is_playing.then_some(now);
+        }
+    }
+
+    // Returns the previous state
+    fn set_reported_idle(&self, is_idle: bool) -> bool {
+        std::mem::replace(&mut self.idle_report.lock().unwrap().is_idle, is_idle)
+    }
+
+    // The monotonic clock stops in suspend unlike the wall clock, returns the time before the jump
+    fn detect_clock_jump(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let monotonic = self.clock.monotonic();
+        let (last_time, last_monotonic) = self
+            .idle_report
+            .lock()
+            .unwrap()
+            .last_status
+            .replace((now, monotonic))?;
+        let jump = (now - last_time) - TimeDelta::from_std(monotonic - last_monotonic).ok()?;
+
+        (jump > MAX_CLOCK_JUMP).then_some(last_time)
+    }
+
+    /// Reports the locked screen as idle since the lock, and active since the unlock.
+    pub async fn set_locked(&self, is_locked: bool, now: DateTime<Utc>) -> anyhow::Result<()> {
+        let was_idle = {
+            let mut report = self.idle_report.lock().unwrap();
+            if report.locked_since.is_some() == is_locked {
+                return Ok(());
+            }
+            report.locked_since = is_locked.then_some(now);
+            report.passive_since = None;
+            // The resume reports the activity
+            if !is_locked && report.suspended_since.is_some() {
+                return Ok(());
+            }
+            if !is_locked {
+                report.active_since = Some(now);
+            }
+            std::mem::replace(&mut report.is_idle, is_locked)
+        };
+
+        if is_locked {
+            debug!("Reporting as locked at {}", now.format("%Y-%m-%d %H:%M:%S"));
+            // An idle user is reported since the last input, the lock starts a new event if marked
+            if was_idle {
+                self.ping_locked(now, TimeDelta::zero()).await
+            } else {
+                self.ping(false, now, TimeDelta::zero()).await?;
+                self.ping_locked(now, TimeDelta::milliseconds(1)).await
+            }
+        } else {
+            debug!(
+                "Reporting as unlocked at {}",
+                now.format("%Y-%m-%d %H:%M:%S")
+            );
+            self.ping_locked(now - TimeDelta::milliseconds(1), TimeDelta::zero())
+                .await?;
+            self.ping(false, now, TimeDelta::zero()).await
+        }
+    }
+
+    /// Reports the suspend as idle, and starts a new active span on resume.
+    pub async fn set_suspended(
+        &self,
+        is_suspended: bool,
+        now: DateTime<Utc>,
+    ) -> anyhow::Result<()> {
+        let (was_idle, locked_since, suspended_since) = {
+            let mut report = self.idle_report.lock().unwrap();
+            report.last_status = None;
+            if report.suspended_since.is_some() == is_suspended {
+                return Ok(());
+            }
+            let suspended_since =
+                std::mem::replace(&mut report.suspended_since, is_suspended.then_some(now));
+            report.passive_since = None;
+            let was_idle = report.is_idle;
+            if is_suspended {
+                report.is_idle = true;
+            } else if report.locked_since.is_none() {
+                report.is_idle = false;
+                report.active_since = Some(now);
+            }
+            (was_idle, report.locked_since, suspended_since)
+        };
+
+        if is_suspended {
+            debug!(
+                "Reporting as suspended at {}",
+                now.format("%Y-%m-%d %H:%M:%S")
+            );
+            if was_idle {
+                return Ok(());
+            }
+            self.ping(false, now, TimeDelta::zero()).await?;
+            self.ping(true, now, TimeDelta::milliseconds(1)).await
+        } else if let Some(locked_since) = locked_since {
+            debug!(
+                "Reporting as resumed and locked at {}",
+                now.format("%Y-%m-%d %H:%M:%S")
+            );
+            self.ping_locked(locked_since, now - locked_since).await
+        } else {
+            debug!(
+                "Reporting as resumed at {}",
+                now.format("%Y-%m-%d %H:%M:%S")
+            );
+            // The idle event started before the suspend is extended over it
+            let suspended_since = suspended_since.unwrap_or(now);
+            self.ping(
+                true,
+                suspended_since,
+                now - suspended_since - TimeDelta::milliseconds(1),
+            )
+            .await?;
+            self.ping(false, now, TimeDelta::zero()).await
         }
     }
 
@@ -255,3 +678,184 @@ impl ReportClient {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::test_server::{Heartbeat, TestServer};
+    use super::*;
+    use crate::clock::SimulatedClock;
+    use crate::config::MediaPlayers;
+    use chrono::TimeZone;
+    use rstest::rstest;
+
+    const FLUSH_TIMEOUT: Duration = Duration::from_secs(5);
+
+    fn start() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 1, 1, 10, 0, 0).unwrap()
+    }
+
+    async fn client(server: &TestServer) -> (ReportClient, Arc<SimulatedClock>) {
+        let config = Config {
+            servers: vec![server.server()],
+            idle_timeout: TimeDelta::seconds(180),
+            idle_timeouts: Vec::new(),
+            poll_time_idle: TimeDelta::seconds(5),
+            poll_time_window: TimeDelta::seconds(1),
+            keep_alive: TimeDelta::seconds(10),
+            idle_backends: Vec::new(),
+            window_backends: Vec::new(),
+            sink: Sink::Servers,
+            hostname: "host".to_string(),
+            idle_bucket_name: "aw-watcher-afk_host".to_string(),
+            active_window_bucket_name: "aw-watcher-window_host".to_string(),
+            process_info: false,
+            screen_lock: false,
+            mark_locked: false,
+            media_idle: MediaIdle::Off,
+            media_players: MediaPlayers::default(),
+            filters: Vec::new(),
+        };
+        let clock = Arc::new(SimulatedClock::new(start()));
+        let client = ReportClient::with_clock(config, Arc::clone(&clock) as Arc<dyn Clock>)
+            .await
+            .unwrap();
+
+        (client, clock)
+    }
+
+    // Milliseconds since the start and the duration in milliseconds
+    fn times(heartbeat: &Heartbeat) -> (i64, i64) {
+        (
+            (heartbeat.event.timestamp - start()).num_milliseconds(),
+            heartbeat.event.duration.num_milliseconds(),
+        )
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn reports_idle_changes() {
+        let server = TestServer::start().await;
+        let (client, _) = client(&server).await;
+        let at = |seconds| start() + TimeDelta::seconds(seconds);
+
+        let statuses = [
+            Status::Active {
+                changed: false,
+                last_input_time: at(0),
+            },
+            Status::Idle {
+                changed: true,
+                last_input_time: at(0),
+                duration: TimeDelta::seconds(200),
+            },
+            Status::Idle {
+                changed: false,
+                last_input_time: at(0),
+                duration: TimeDelta::seconds(205),
+            },
+            Status::Active {
+                changed: true,
+                last_input_time: at(210),
+            },
+        ];
+        // Queued heartbeats would be merged as the server does, wait to see every one of them
+        for status in statuses {
+            client.handle_idle_status(status).await.unwrap();
+            client.flush(FLUSH_TIMEOUT).await.unwrap();
+        }
+
+        let heartbeats = server.wait_for_heartbeats(6).await;
+        let events: Vec<_> = heartbeats
+            .iter()
+            .map(|heartbeat| {
+                (
+                    heartbeat.event.data["status"].as_str().unwrap(),
+                    times(heartbeat),
+                )
+            })
+            .collect();
+        assert_eq!(
+            vec![
+                ("not-afk", (0, 0)),
+                // The idle event starts at the last input, 1ms longer to be the latest one
+                ("not-afk", (0, 0)),
+                ("afk", (0, 200_001)),
+                ("afk", (0, 205_000)),
+                // The idle event ends 1ms before the input
+                ("afk", (209_999, 0)),
+                ("not-afk", (210_000, 0)),
+            ],
+            events
+        );
+        assert!(heartbeats
+            .iter()
+            .all(|heartbeat| heartbeat.bucket == "aw-watcher-afk_host"
+                && heartbeat.pulsetime == 185.0));
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn reports_active_windows() {
+        let server = TestServer::start().await;
+        let (client, clock) = client(&server).await;
+
+        client.send_active_window("firefox", "Docs").await.unwrap();
+        client.flush(FLUSH_TIMEOUT).await.unwrap();
+        clock.advance(Duration::from_secs(3));
+        client.send_active_window("firefox", "Docs").await.unwrap();
+        client.flush(FLUSH_TIMEOUT).await.unwrap();
+        clock.advance(Duration::from_secs(2));
+        client.send_active_window("code", "main.rs").await.unwrap();
+
+        let heartbeats = server.wait_for_heartbeats(4).await;
+        let events: Vec<_> = heartbeats
+            .iter()
+            .map(|heartbeat| {
+                (
+                    heartbeat.event.data["app"].as_str().unwrap(),
+                    times(heartbeat),
+                )
+            })
+            .collect();
+        assert_eq!(
+            vec![
+                ("firefox", (0, 0)),
+                ("firefox", (3000, 0)),
+                // The previous window is closed at the switch
+                ("firefox", (5000, 0)),
+                ("code", (5000, 0)),
+            ],
+            events
+        );
+        assert_eq!(
+            vec!["aw-watcher-afk_host", "aw-watcher-window_host"],
+            server.buckets()
+        );
+        assert!(heartbeats
+            .iter()
+            .all(|heartbeat| heartbeat.bucket == "aw-watcher-window_host"
+                && heartbeat.pulsetime == 2.0));
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn retries_failed_requests() {
+        let server = TestServer::start().await;
+        server.fail_requests(2);
+        let (client, _) = client(&server).await;
+        assert_eq!(
+            vec!["aw-watcher-afk_host", "aw-watcher-window_host"],
+            server.buckets()
+        );
+
+        server.fail_requests(3);
+        client
+            .ping(false, start(), TimeDelta::zero())
+            .await
+            .unwrap();
+
+        let heartbeats = server.wait_for_heartbeats(1).await;
+        assert_eq!(1, heartbeats.len());
+        assert_eq!((0, 0), times(&heartbeats[0]));
+    }
+}
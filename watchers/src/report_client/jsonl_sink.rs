@@ -0,0 +1,132 @@
+use super::sink::EventSink;
+use anyhow::Context;
+use async_trait::async_trait;
+use aw_client_rust::{Bucket, Event as AwEvent};
+use serde::Serialize;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// A line of the file, either `{"bucket": {...}}` or `{"heartbeat": {...}}`
+/// with the same bucket and event objects as in ActivityWatch REST API.
+#[derive(Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Record<'a> {
+    Bucket(&'a Bucket),
+    Heartbeat {
+        bucket: &'a str,
+        pulsetime: f64,
+        event: &'a AwEvent,
+    },
+}
+
+/// Appends buckets and heartbeats to a JSON lines file to be imported later.
+pub struct JsonlSink {
+    path: PathBuf,
+    file: Mutex<File>,
+}
+
+impl JsonlSink {
+    pub fn new(path: &Path) -> anyhow::Result<Self> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            file: Mutex::new(file),
+        })
+    }
+
+    fn write(&self, record: &Record) -> anyhow::Result<()> {
+        let mut line = serde_json::to_string(record)?;
+        line.push('\n');
+
+        let mut file = self.file.lock().unwrap();
+        file.write_all(line.as_bytes())
+            .and_then(|()| file.flush())
+            .with_context(|| format!("Failed to write to {}", self.path.display()))
+    }
+}
+
+#[async_trait]
+impl EventSink for JsonlSink {
+    async fn create_bucket(&self, bucket: &Bucket) -> anyhow::Result<()> {
+        self.write(&Record::Bucket(bucket))
+    }
+
+    async fn heartbeat(
+        &self,
+        bucket_name: &str,
+        event: &AwEvent,
+        pulsetime: f64,
+    ) -> anyhow::Result<()> {
+        self.write(&Record::Heartbeat {
+            bucket: bucket_name,
+            pulsetime,
+            event,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aw_client_rust::BucketMetadata;
+    use chrono::{TimeDelta, TimeZone, Utc};
+    use rstest::rstest;
+    use serde_json::{Map, Value};
+    use tempfile::tempdir;
+
+    #[rstest]
+    #[tokio::test]
+    async fn writes_lines() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("events.jsonl");
+        let sink = JsonlSink::new(&path).unwrap();
+
+        let mut data = Map::new();
+        data.insert("status".to_string(), Value::String("afk".to_string()));
+        let event = AwEvent {
+            id: None,
+            timestamp: Utc.with_ymd_and_hms(2024, 1, 1, 10, 0, 0).unwrap(),
+            duration: TimeDelta::seconds(5),
+            data,
+        };
+        let bucket = Bucket {
+            bid: None,
+            id: "aw-watcher-afk_host".to_string(),
+            _type: "afkstatus".to_string(),
+            client: "awatcher".to_string(),
+            hostname: "host".to_string(),
+            created: Some(Utc::now()),
+            data: Map::new(),
+            metadata: BucketMetadata::default(),
+            events: None,
+            last_updated: None,
+        };
+        sink.create_bucket(&bucket).await.unwrap();
+        sink.heartbeat("aw-watcher-afk_host", &event, 185.0)
+            .await
+            .unwrap();
+
+        let content = fs::read_to_string(&path).unwrap();
+        let lines: Vec<Value> = content
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(2, lines.len());
+        assert_eq!("aw-watcher-afk_host", lines[0]["bucket"]["id"]);
+        assert_eq!("afkstatus", lines[0]["bucket"]["type"]);
+        assert_eq!("host", lines[0]["bucket"]["hostname"]);
+        assert_eq!("aw-watcher-afk_host", lines[1]["heartbeat"]["bucket"]);
+        assert_eq!(185.0, lines[1]["heartbeat"]["pulsetime"]);
+        assert_eq!("afk", lines[1]["heartbeat"]["event"]["data"]["status"]);
+    }
+}
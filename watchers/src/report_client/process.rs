@@ -0,0 +1,309 @@
+/*
+ * Process details of the window from /proc by its PID.
+ * A terminal is recognized by its children which are session leaders with a controlling terminal (shells),
+ * the foreground process group of their terminal is what the user runs in it.
+ */
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+// Enough to skip wrappers like bwrap or flatpak-spawn, systemd and init are not interesting
+const MAX_PARENTS: usize = 8;
+
+struct Stat {
+    comm: String,
+    ppid: u32,
+    session: u32,
+    tty_nr: i32,
+    tpgid: i32,
+    start_time: u64,
+}
+
+pub struct ProcessReader {
+    proc_root: PathBuf,
+}
+
+impl Default for ProcessReader {
+    fn default() -> Self {
+        Self::new("/proc")
+    }
+}
+
+impl ProcessReader {
+    pub fn new(proc_root: impl Into<PathBuf>) -> Self {
+        Self {
+            proc_root: proc_root.into(),
+        }
+    }
+
+    /// Adds "exe", "cwd" and "parents", and "fg_command", "fg_exe" and "fg_cwd" for terminals.
+    /// The process may be inaccessible or gone, then only the available data is added.
+    pub fn enrich(&self, pid: u32, extra: &mut HashMap<String, String>) {
+        if let Some(exe) = self.link(pid, "exe") {
+            extra.insert("exe".to_string(), exe);
+        }
+        if let Some(cwd) = self.link(pid, "cwd") {
+            extra.insert("cwd".to_string(), cwd);
+        }
+        let parents = self.parents(pid);
+        if !parents.is_empty() {
+            extra.insert("parents".to_string(), parents.join(","));
+        }
+
+        if let Some((foreground, stat)) = self.terminal_foreground(pid) {
+            let command = self.command_line(foreground).unwrap_or(stat.comm);
+            extra.insert("fg_command".to_string(), command);
+            if let Some(exe) = self.link(foreground, "exe") {
+                extra.insert("fg_exe".to_string(), exe);
+            }
+            if let Some(cwd) = self.link(foreground, "cwd") {
+                extra.insert("fg_cwd".to_string(), cwd);
+            }
+        }
+    }
+
+    fn path(&self, pid: u32, name: &str) -> PathBuf {
+        self.proc_root.join(pid.to_string()).join(name)
+    }
+
+    fn link(&self, pid: u32, name: &str) -> Option<String> {
+        let target = fs::read_link(self.path(pid, name)).ok()?;
+        Some(target.to_string_lossy().into_owned())
+    }
+
+    // The command name in stat is cut to 15 characters, the command line is complete
+    fn command_line(&self, pid: u32) -> Option<String> {
+        let cmdline = fs::read(self.path(pid, "cmdline")).ok()?;
+        let args: Vec<_> = cmdline
+            .split(|&byte| byte == 0)
+            .filter(|arg| !arg.is_empty())
+            .map(String::from_utf8_lossy)
+            .collect();
+
+        // Empty for zombies and kernel threads
+        (!args.is_empty()).then(|| args.join(" "))
+    }
+
+    fn stat(&self, pid: u32) -> Option<Stat> {
+        parse_stat(&fs::read_to_string(self.path(pid, "stat")).ok()?)
+    }
+
+    // Command names of the ancestors, the closest first
+    fn parents(&self, pid: u32) -> Vec<String> {
+        let mut parents = Vec::new();
+        let mut ppid = self.stat(pid).map(|stat| stat.ppid);
+        while let Some(pid) = ppid.filter(|&pid| pid > 1 && parents.len() < MAX_PARENTS) {
+            let Some(stat) = self.stat(pid) else {
+                break;
+            };
+            ppid = Some(stat.ppid);
+            parents.push(stat.comm);
+        }
+        parents
+    }
+
+    fn children(&self, pid: u32) -> Vec<u32> {
+        let Ok(tasks) = fs::read_dir(self.path(pid, "task")) else {
+            return Vec::new();
+        };
+        tasks
+            .filter_map(Result::ok)
+            .filter_map(|task| fs::read_to_string(task.path().join("children")).ok())
+            .flat_map(|children| {
+                children
+                    .split_whitespace()
+                    .filter_map(|child| child.parse().ok())
+                    .collect::<Vec<u32>>()
+            })
+            .collect()
+    }
+
+    // With several tabs, the tab where a command was started last is assumed to be active
+    fn terminal_foreground(&self, pid: u32) -> Option<(u32, Stat)> {
+        self.children(pid)
+            .into_iter()
+            .filter_map(|child| {
+                let shell = self.stat(child)?;
+                if shell.session != child || shell.tty_nr == 0 {
+                    return None;
+                }
+                let foreground = u32::try_from(shell.tpgid).ok().filter(|&pgid| pgid > 0)?;
+                Some((foreground, self.stat(foreground)?))
+            })
+            .max_by_key(|(_, stat)| stat.start_time)
+    }
+}
+
+// The command name is in parentheses and may contain spaces and parentheses itself
+fn parse_stat(stat: &str) -> Option<Stat> {
+    let (head, tail) = stat.rsplit_once(')')?;
+    let (_, comm) = head.split_once('(')?;
+    let fields: Vec<&str> = tail.split_whitespace().collect();
+
+    Some(Stat {
+        comm: comm.to_string(),
+        ppid: fields.get(1)?.parse().ok()?,
+        session: fields.get(3)?.parse().ok()?,
+        tty_nr: fields.get(4)?.parse().ok()?,
+        tpgid: fields.get(5)?.parse().ok()?,
+        start_time: fields.get(19)?.parse().ok()?,
+    })
+}
+
+/// Reads the PID which the watchers report in the extra data.
+pub fn window_pid(extra: &HashMap<String, String>) -> Option<u32> {
+    extra
+        .get("pid")
+        .and_then(|pid| pid.parse().ok())
+        .filter(|&pid| pid > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use std::os::unix::fs::symlink;
+    use std::path::Path;
+
+    fn add_process(root: &Path, pid: u32, stat: &str, exe: &str, cwd: &str) {
+        let dir = root.join(pid.to_string());
+        fs::create_dir_all(dir.join("task").join(pid.to_string())).unwrap();
+        fs::write(dir.join("stat"), format!("{pid} {stat}")).unwrap();
+        symlink(exe, dir.join("exe")).unwrap();
+        symlink(cwd, dir.join("cwd")).unwrap();
+    }
+
+    fn set_children(root: &Path, pid: u32, children: &str) {
+        let task = root
+            .join(pid.to_string())
+            .join("task")
+            .join(pid.to_string());
+        fs::write(task.join("children"), children).unwrap();
+    }
+
+    // state ppid pgrp session tty_nr tpgid, then 13 fields before starttime
+    fn stat(comm: &str, ids: &str, start_time: u64) -> String {
+        format!("({comm}) S {ids} 0 0 0 0 0 0 0 0 0 0 0 0 0 {start_time} 0")
+    }
+
+    #[rstest]
+    fn parses_stat() {
+        let stat = parse_stat(
+            "42 (tmux: server (1)) S 1 42 42 34816 50 4194560 0 0 0 0 0 0 0 0 20 0 1 0 777 0",
+        )
+        .unwrap();
+        assert_eq!("tmux: server (1)", stat.comm);
+        assert_eq!(1, stat.ppid);
+        assert_eq!(42, stat.session);
+        assert_eq!(34816, stat.tty_nr);
+        assert_eq!(50, stat.tpgid);
+        assert_eq!(777, stat.start_time);
+    }
+
+    #[rstest]
+    fn finds_terminal_foreground() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        add_process(
+            root,
+            10,
+            &stat("systemd", "1 10 10 0 -1", 1),
+            "/usr/lib/systemd/systemd",
+            "/",
+        );
+        add_process(
+            root,
+            20,
+            &stat("konsole", "10 20 20 0 -1", 2),
+            "/usr/bin/konsole",
+            "/home/user",
+        );
+        // Two tabs, vim is started later than cargo
+        add_process(
+            root,
+            30,
+            &stat("bash", "20 30 30 34816 40", 3),
+            "/usr/bin/bash",
+            "/home/user/a",
+        );
+        add_process(
+            root,
+            31,
+            &stat("bash", "20 31 31 34817 41", 4),
+            "/usr/bin/bash",
+            "/home/user/b",
+        );
+        add_process(
+            root,
+            40,
+            &stat("cargo", "30 40 30 34816 40", 5),
+            "/usr/bin/cargo",
+            "/home/user/a",
+        );
+        add_process(
+            root,
+            41,
+            &stat("vim", "31 41 31 34817 41", 6),
+            "/usr/bin/vim",
+            "/home/user/b/src",
+        );
+        set_children(root, 20, "30 31");
+
+        let mut extra = HashMap::new();
+        ProcessReader::new(root).enrich(20, &mut extra);
+
+        assert_eq!("/usr/bin/konsole", extra["exe"]);
+        assert_eq!("/home/user", extra["cwd"]);
+        assert_eq!("systemd", extra["parents"]);
+        assert_eq!("vim", extra["fg_command"]);
+        assert_eq!("/usr/bin/vim", extra["fg_exe"]);
+        assert_eq!("/home/user/b/src", extra["fg_cwd"]);
+    }
+
+    #[rstest]
+    fn reads_foreground_command_line() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        add_process(
+            root,
+            20,
+            &stat("alacritty", "1 20 20 0 -1", 2),
+            "/usr/bin/alacritty",
+            "/home/user",
+        );
+        add_process(
+            root,
+            30,
+            &stat("zsh", "20 30 30 34816 40", 3),
+            "/usr/bin/zsh",
+            "/home/user",
+        );
+        add_process(
+            root,
+            40,
+            &stat("long-running-sc", "30 40 30 34816 40", 4),
+            "/usr/bin/python3",
+            "/home/user",
+        );
+        fs::write(
+            root.join("40").join("cmdline"),
+            "long-running-script\0--verbose\0",
+        )
+        .unwrap();
+        set_children(root, 20, "30");
+
+        let mut extra = HashMap::new();
+        ProcessReader::new(root).enrich(20, &mut extra);
+
+        assert_eq!("long-running-script --verbose", extra["fg_command"]);
+    }
+
+    #[rstest]
+    fn skips_missing_process() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut extra = HashMap::new();
+        ProcessReader::new(dir.path()).enrich(20, &mut extra);
+
+        assert!(extra.is_empty());
+    }
+}
@@ -0,0 +1,196 @@
+use crate::clock::Clock;
+use crate::config::RetryPolicy;
+use std::error::Error;
+use std::fmt::Display;
+use std::future::Future;
+use std::io;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorClass {
+    /// The server may accept the same request later, e.g. it is restarting or overloaded.
+    Transient,
+    /// Repeating the request doesn't help.
+    Fatal,
+}
+
+pub trait Classify {
+    fn classify(&self) -> ErrorClass;
+}
+
+impl Classify for reqwest::Error {
+    fn classify(&self) -> ErrorClass {
+        if let Some(status) = self.status() {
+            return if status.is_server_error()
+                || status == reqwest::StatusCode::TOO_MANY_REQUESTS
+                || status == reqwest::StatusCode::REQUEST_TIMEOUT
+            {
+                ErrorClass::Transient
+            } else {
+                ErrorClass::Fatal
+            };
+        }
+        if self.is_builder() || self.is_redirect() || self.is_decode() {
+            return ErrorClass::Fatal;
+        }
+        // Refused connections, DNS failures and timeouts are reported as connect or timeout errors,
+        // the connection reset in the middle of a request is reported as a request error.
+        if self.is_connect() || self.is_timeout() || self.is_request() || self.is_body() {
+            return ErrorClass::Transient;
+        }
+
+        classify_io_source(self)
+    }
+}
+
+fn classify_io_source(error: &(dyn Error + 'static)) -> ErrorClass {
+    let mut source = error.source();
+    while let Some(error) = source {
+        if let Some(io_error) = error.downcast_ref::<io::Error>() {
+            return match io_error.kind() {
+                io::ErrorKind::ConnectionRefused
+                | io::ErrorKind::ConnectionReset
+                | io::ErrorKind::ConnectionAborted
+                | io::ErrorKind::NotConnected
+                | io::ErrorKind::BrokenPipe
+                | io::ErrorKind::TimedOut
+                | io::ErrorKind::Interrupted
+                | io::ErrorKind::UnexpectedEof => ErrorClass::Transient,
+                _ => ErrorClass::Fatal,
+            };
+        }
+        source = error.source();
+    }
+
+    ErrorClass::Fatal
+}
+
+impl RetryPolicy {
+    /// Exponential backoff for the retry number `attempt` (starting from 0) with a random jitter.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let backoff = self
+            .initial_backoff
+            .saturating_mul(2_u32.saturating_pow(attempt))
+            .min(self.max_backoff);
+
+        let factor = 1.0 + self.jitter * (2.0 * fastrand::f64() - 1.0);
+
+        backoff.mul_f64(factor.max(0.0))
+    }
+}
+
+pub async fn run_with_retries<F, Fut, T, E>(
+    policy: &RetryPolicy,
+    clock: &dyn Clock,
+    f: F,
+) -> Result<T, E>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+    E: Classify + Display,
+{
+    for attempt in 0..policy.attempts {
+        match f().await {
+            Ok(val) => return Ok(val),
+            Err(e) if e.classify() == ErrorClass::Transient => {
+                let delay = policy.delay(attempt);
+                warn!(
+                    "Failed to reach the server on attempt #{}, retrying in {delay:?}: {e}",
+                    attempt + 1
+                );
+
+                clock.sleep(delay).await;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    f().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::SystemClock;
+    use rstest::rstest;
+    use std::cell::Cell;
+
+    fn policy(jitter: f64) -> RetryPolicy {
+        RetryPolicy {
+            attempts: 3,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(3),
+            jitter,
+        }
+    }
+
+    #[derive(Debug)]
+    struct TestError(ErrorClass);
+
+    impl Display for TestError {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "{:?}", self.0)
+        }
+    }
+
+    impl Classify for TestError {
+        fn classify(&self) -> ErrorClass {
+            self.0
+        }
+    }
+
+    #[rstest]
+    #[case(0, 1)]
+    #[case(1, 2)]
+    #[case(2, 3)]
+    #[case(10, 3)]
+    fn delay_without_jitter(#[case] attempt: u32, #[case] expected_ms: u64) {
+        assert_eq!(
+            Duration::from_millis(expected_ms),
+            policy(0.0).delay(attempt)
+        );
+    }
+
+    #[rstest]
+    fn delay_with_jitter() {
+        let policy = RetryPolicy {
+            initial_backoff: Duration::from_secs(10),
+            max_backoff: Duration::from_secs(10),
+            ..policy(0.5)
+        };
+        for _ in 0..100 {
+            let delay = policy.delay(0);
+            assert!(delay >= Duration::from_secs(5) && delay <= Duration::from_secs(15));
+        }
+    }
+
+    #[rstest]
+    #[case::transient(ErrorClass::Transient, 4)]
+    #[case::fatal(ErrorClass::Fatal, 1)]
+    #[tokio::test]
+    async fn retries(#[case] class: ErrorClass, #[case] expected_calls: u32) {
+        let calls = Cell::new(0);
+        let result: Result<(), TestError> =
+            run_with_retries(&policy(0.0), &SystemClock::default(), || async {
+                calls.set(calls.get() + 1);
+                Err(TestError(class))
+            })
+            .await;
+
+        assert!(result.is_err());
+        assert_eq!(expected_calls, calls.get());
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn connection_refused_is_transient() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        drop(listener);
+
+        let error = reqwest::get(format!("http://{address}/"))
+            .await
+            .unwrap_err();
+        assert_eq!(ErrorClass::Transient, error.classify());
+    }
+}
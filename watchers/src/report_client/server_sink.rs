@@ -0,0 +1,579 @@
+use super::retry::{run_with_retries, Classify, ErrorClass};
+use super::sink::EventSink;
+use super::spool::{merge_heartbeat, Spool, SpooledHeartbeat};
+use crate::clock::Clock;
+use crate::config::{RetryPolicy, Server};
+use anyhow::{anyhow, Context};
+use async_trait::async_trait;
+use aw_client_rust::{Bucket, Event as AwEvent};
+use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION};
+use serde::Serialize;
+use std::collections::{HashSet, VecDeque};
+use std::sync::{Arc, Mutex as SyncMutex};
+use std::time::Duration;
+use tokio::sync::{Mutex, Notify};
+
+// Distinct events waiting for the server, the repeated ones are merged into them
+const MAX_QUEUED_HEARTBEATS: usize = 100;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Sends the data to ActivityWatch server, keeping it in the spool while the server is unavailable.
+///
+/// Heartbeats are delivered from a background task, so a slow or unavailable server
+/// doesn't delay the watchers and the other servers. Delivery failures are returned
+/// from the next heartbeat, unless the failed heartbeat is spooled.
+pub struct ServerSink {
+    target: Arc<Target>,
+    queue: Arc<Queue>,
+}
+
+struct Target {
+    address: String,
+    // aw-client-rust ignores the response status, which tells if the request is worth repeating
+    client: reqwest::Client,
+    buckets_url: String,
+    retry: RetryPolicy,
+    clock: Arc<dyn Clock>,
+    state: Mutex<ServerState>,
+    spool: Option<SyncMutex<Spool>>,
+}
+
+struct ServerState {
+    is_available: bool,
+    pending_buckets: Vec<Bucket>,
+    // Buckets which the server refused to create, their heartbeats are dropped
+    rejected_buckets: HashSet<String>,
+}
+
+#[derive(Default)]
+struct Queue {
+    state: SyncMutex<QueueState>,
+    added: Notify,
+    drained: Notify,
+}
+
+#[derive(Default)]
+struct QueueState {
+    heartbeats: VecDeque<SpooledHeartbeat>,
+    is_sending: bool,
+    is_closed: bool,
+    // The latest delivery failure which is not reported to the watchers yet
+    failure: Option<anyhow::Error>,
+}
+
+impl ServerSink {
+    pub fn new(server: &Server, clock: Arc<dyn Clock>) -> anyhow::Result<Self> {
+        let address = format!("{}:{}", server.client_host(), server.port);
+        let mut headers = HeaderMap::new();
+        if let Some(api_key) = &server.api_key {
+            let mut value = HeaderValue::from_str(&format!("Bearer {api_key}"))
+                .with_context(|| format!("Invalid API key for {address}"))?;
+            value.set_sensitive(true);
+            headers.insert(AUTHORIZATION, value);
+        }
+        let client = reqwest::Client::builder()
+            .default_headers(headers)
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            .with_context(|| format!("Failed to create a client for {address}"))?;
+
+        let spool = if let Some(spool_file) = &server.spool_file {
+            Some(SyncMutex::new(Spool::load(spool_file.clone())?))
+        } else {
+            None
+        };
+
+        let target = Arc::new(Target {
+            buckets_url: format!("http://{address}/api/0/buckets"),
+            address,
+            client,
+            retry: server.retry.clone(),
+            clock,
+            state: Mutex::new(ServerState {
+                is_available: true,
+                pending_buckets: Vec::new(),
+                rejected_buckets: HashSet::new(),
+            }),
+            spool,
+        });
+        let queue = Arc::new(Queue::default());
+
+        let worker_target = Arc::clone(&target);
+        let worker_queue = Arc::clone(&queue);
+        tokio::spawn(async move {
+            while let Some(heartbeat) = worker_queue.next().await {
+                let result = worker_target.heartbeat(&heartbeat).await;
+                if let Err(e) = &result {
+                    error!(
+                        "Failed to send heartbeat to {}: {e:?}",
+                        worker_target.address
+                    );
+                }
+                worker_queue.finish(result.err());
+            }
+        });
+
+        Ok(Self { target, queue })
+    }
+}
+
+impl Drop for ServerSink {
+    fn drop(&mut self) {
+        self.queue.close();
+    }
+}
+
+impl Queue {
+    // Returns the oldest heartbeat if the queue overflows.
+    fn push(&self, heartbeat: SpooledHeartbeat) -> Option<SpooledHeartbeat> {
+        let mut state = self.state.lock().unwrap();
+        let last = state
+            .heartbeats
+            .iter_mut()
+            .rev()
+            .find(|last| last.bucket == heartbeat.bucket);
+        if last.is_some_and(|last| merge_heartbeat(last, &heartbeat.event, heartbeat.pulsetime)) {
+            return None;
+        }
+
+        state.heartbeats.push_back(heartbeat);
+        self.added.notify_one();
+        if state.heartbeats.len() > MAX_QUEUED_HEARTBEATS {
+            state.heartbeats.pop_front()
+        } else {
+            None
+        }
+    }
+
+    async fn next(&self) -> Option<SpooledHeartbeat> {
+        loop {
+            let added = self.added.notified();
+            {
+                let mut state = self.state.lock().unwrap();
+                if let Some(heartbeat) = state.heartbeats.pop_front() {
+                    state.is_sending = true;
+                    return Some(heartbeat);
+                }
+                if state.is_closed {
+                    return None;
+                }
+            }
+            added.await;
+        }
+    }
+
+    fn finish(&self, failure: Option<anyhow::Error>) {
+        let mut state = self.state.lock().unwrap();
+        state.is_sending = false;
+        if failure.is_some() {
+            state.failure = failure;
+        }
+        if state.heartbeats.is_empty() {
+            self.drained.notify_waiters();
+        }
+    }
+
+    async fn wait_drained(&self) {
+        loop {
+            let drained = self.drained.notified();
+            {
+                let state = self.state.lock().unwrap();
+                if state.heartbeats.is_empty() && !state.is_sending {
+                    return;
+                }
+            }
+            drained.await;
+        }
+    }
+
+    fn take_failure(&self) -> Option<anyhow::Error> {
+        self.state.lock().unwrap().failure.take()
+    }
+
+    fn take_all(&self) -> VecDeque<SpooledHeartbeat> {
+        std::mem::take(&mut self.state.lock().unwrap().heartbeats)
+    }
+
+    fn close(&self) {
+        self.state.lock().unwrap().is_closed = true;
+        self.added.notify_one();
+    }
+}
+
+impl Target {
+    // Replays the spooled heartbeats before the new one to keep the order of events.
+    async fn deliver(
+        &self,
+        state: &mut ServerState,
+        heartbeat: &SpooledHeartbeat,
+    ) -> anyhow::Result<()> {
+        while let Some(bucket) = state.pending_buckets.first() {
+            debug!("Creating bucket {}", bucket.id);
+            match self.post_bucket(bucket).await {
+                Ok(()) => {}
+                // Don't block the heartbeats of the other buckets
+                Err(e) if e.classify() == ErrorClass::Fatal => {
+                    error!(
+                        "Server {} rejected bucket {}, its heartbeats are dropped: {e}",
+                        self.address, bucket.id
+                    );
+                    state.rejected_buckets.insert(bucket.id.clone());
+                }
+                Err(e) => return Err(e.into()),
+            }
+            state.pending_buckets.remove(0);
+        }
+
+        if let Some(spool) = &self.spool {
+            let total = spool.lock().unwrap().len();
+            if total > 0 {
+                let result = self.replay(spool).await;
+                // The replayed heartbeats stay in the file until it's rewritten
+                spool.lock().unwrap().compact()?;
+                result?;
+                info!("Replayed {total} spooled heartbeats");
+            }
+        }
+
+        if state.rejected_buckets.contains(&heartbeat.bucket) {
+            trace!(
+                "Dropping heartbeat for rejected bucket {}",
+                heartbeat.bucket
+            );
+            return Ok(());
+        }
+        let request = || self.post_heartbeat(heartbeat);
+        if state.is_available {
+            run_with_retries(&self.retry, self.clock.as_ref(), request).await?;
+        } else {
+            // Don't wait for the retries of every queued heartbeat while the server is down
+            request().await?;
+        }
+        Ok(())
+    }
+
+    async fn replay(&self, spool: &SyncMutex<Spool>) -> anyhow::Result<()> {
+        // Only this task removes from the spool, the watchers may append while it is sent
+        loop {
+            let front = spool.lock().unwrap().front().cloned();
+            let Some(spooled) = front else {
+                return Ok(());
+            };
+            match self.post_heartbeat(&spooled).await {
+                Ok(()) => {}
+                // The server will never accept it, don't block the rest of the spool
+                Err(e) if e.classify() == ErrorClass::Fatal => {
+                    error!("Dropping spooled heartbeat for {}: {e}", spooled.bucket);
+                }
+                Err(e) => return Err(e.into()),
+            }
+            spool.lock().unwrap().pop_front();
+        }
+    }
+
+    async fn create_bucket(&self, bucket: &Bucket) -> anyhow::Result<()> {
+        let mut state = self.state.lock().await;
+
+        if !state.is_available {
+            state.pending_buckets.push(bucket.clone());
+            return Ok(());
+        }
+
+        let request = || self.post_bucket(bucket);
+        match run_with_retries(&self.retry, self.clock.as_ref(), request).await {
+            Ok(()) => Ok(()),
+            // The bucket is created before the next heartbeat
+            Err(e) if e.classify() == ErrorClass::Transient => {
+                warn!(
+                    "Server {} is not available, bucket {} is created when it is: {e}",
+                    self.address, bucket.id
+                );
+                state.is_available = false;
+                state.pending_buckets.push(bucket.clone());
+                Ok(())
+            }
+            Err(e) => Err(e).with_context(|| {
+                format!("Failed to create bucket {} at {}", bucket.id, self.address)
+            }),
+        }
+    }
+
+    async fn heartbeat(&self, heartbeat: &SpooledHeartbeat) -> anyhow::Result<()> {
+        let mut state = self.state.lock().await;
+
+        match self.deliver(&mut state, heartbeat).await {
+            Ok(()) => {
+                if !state.is_available {
+                    info!("Server {} is available again", self.address);
+                    state.is_available = true;
+                }
+                Ok(())
+            }
+            Err(e) if is_fatal(&e) => Err(e),
+            Err(e) => {
+                if state.is_available {
+                    warn!("Server {} is not available: {e}", self.address);
+                    state.is_available = false;
+                } else {
+                    debug!("Server {} is still not available: {e}", self.address);
+                }
+                if self.spool.is_some() {
+                    self.spool_heartbeat(heartbeat)
+                } else {
+                    Err(e)
+                }
+            }
+        }
+    }
+
+    async fn post_bucket(&self, bucket: &Bucket) -> Result<(), reqwest::Error> {
+        let url = format!("{}/{}", self.buckets_url, bucket.id);
+        self.post(url, bucket).await
+    }
+
+    async fn post_heartbeat(&self, heartbeat: &SpooledHeartbeat) -> Result<(), reqwest::Error> {
+        let url = format!(
+            "{}/{}/heartbeat?pulsetime={}",
+            self.buckets_url, heartbeat.bucket, heartbeat.pulsetime
+        );
+        self.post(url, &heartbeat.event).await
+    }
+
+    async fn post(&self, url: String, body: &impl Serialize) -> Result<(), reqwest::Error> {
+        self.client
+            .post(url)
+            .json(body)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    fn spool_heartbeat(&self, heartbeat: &SpooledHeartbeat) -> anyhow::Result<()> {
+        let spool = self
+            .spool
+            .as_ref()
+            .ok_or_else(|| anyhow!("Heartbeat for {} is lost", heartbeat.bucket))?;
+        spool
+            .lock()
+            .unwrap()
+            .push(&heartbeat.bucket, &heartbeat.event, heartbeat.pulsetime)
+            .with_context(|| "Failed to spool heartbeat")
+    }
+}
+
+#[async_trait]
+impl EventSink for ServerSink {
+    async fn create_bucket(&self, bucket: &Bucket) -> anyhow::Result<()> {
+        self.target.create_bucket(bucket).await
+    }
+
+    async fn heartbeat(
+        &self,
+        bucket_name: &str,
+        event: &AwEvent,
+        pulsetime: f64,
+    ) -> anyhow::Result<()> {
+        let overflow = self.queue.push(SpooledHeartbeat {
+            bucket: bucket_name.to_string(),
+            event: event.clone(),
+            pulsetime,
+        });
+        if let Some(overflow) = overflow {
+            self.target.spool_heartbeat(&overflow).with_context(|| {
+                format!("Too many heartbeats are queued for {}", self.target.address)
+            })?;
+        }
+
+        match self.queue.take_failure() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    async fn flush(&self, timeout: Duration) -> anyhow::Result<()> {
+        let is_drained = tokio::select! {
+            () = self.queue.wait_drained() => true,
+            () = self.target.clock.sleep(timeout) => false,
+        };
+        if is_drained {
+            return Ok(());
+        }
+
+        let remaining = self.queue.take_all();
+        if self.target.spool.is_none() && !remaining.is_empty() {
+            return Err(anyhow!(
+                "{} heartbeats are not delivered to {}",
+                remaining.len(),
+                self.target.address
+            ));
+        }
+        for heartbeat in &remaining {
+            self.target.spool_heartbeat(heartbeat)?;
+        }
+        Ok(())
+    }
+}
+
+// Spooling doesn't help if the server rejects the request itself.
+fn is_fatal(error: &anyhow::Error) -> bool {
+    error
+        .downcast_ref::<reqwest::Error>()
+        .is_some_and(|e| e.classify() == ErrorClass::Fatal)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_server::{Failure, TestServer};
+    use super::*;
+    use crate::clock::SystemClock;
+    use aw_client_rust::BucketMetadata;
+    use chrono::{TimeDelta, TimeZone, Utc};
+    use rstest::rstest;
+    use serde_json::{Map, Value};
+
+    fn heartbeat(seconds: i64, app: &str) -> SpooledHeartbeat {
+        let mut data = Map::new();
+        data.insert("app".to_string(), Value::String(app.to_string()));
+
+        SpooledHeartbeat {
+            bucket: "window".to_string(),
+            pulsetime: 2.0,
+            event: AwEvent {
+                id: None,
+                timestamp: Utc.with_ymd_and_hms(2024, 1, 1, 10, 0, 0).unwrap()
+                    + TimeDelta::seconds(seconds),
+                duration: TimeDelta::zero(),
+                data,
+            },
+        }
+    }
+
+    fn bucket(id: &str) -> Bucket {
+        Bucket {
+            bid: None,
+            id: id.to_string(),
+            _type: "currentwindow".to_string(),
+            client: "awatcher".to_string(),
+            hostname: "host".to_string(),
+            created: None,
+            data: Map::new(),
+            metadata: BucketMetadata::default(),
+            events: None,
+            last_updated: None,
+        }
+    }
+
+    async fn send(sink: &ServerSink, heartbeat: SpooledHeartbeat) -> anyhow::Result<()> {
+        sink.heartbeat(&heartbeat.bucket, &heartbeat.event, heartbeat.pulsetime)
+            .await
+    }
+
+    #[rstest]
+    fn coalesces_queued_heartbeats() {
+        let queue = Queue::default();
+
+        for seconds in 0..3 {
+            assert!(queue.push(heartbeat(seconds, "code")).is_none());
+        }
+        assert_eq!(1, queue.state.lock().unwrap().heartbeats.len());
+
+        for seconds in 1..MAX_QUEUED_HEARTBEATS as i64 {
+            assert!(queue
+                .push(heartbeat(seconds, &seconds.to_string()))
+                .is_none());
+        }
+        let overflow = queue.push(heartbeat(1000, "firefox")).unwrap();
+        assert_eq!(TimeDelta::seconds(2), overflow.event.duration);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn creates_failed_buckets_before_heartbeats() {
+        let server = TestServer::start().await;
+        let sink = ServerSink::new(&server.server(), Arc::new(SystemClock::default())).unwrap();
+
+        // All retries fail
+        server.fail_requests(4);
+        sink.create_bucket(&bucket("window")).await.unwrap();
+        assert!(server.buckets().is_empty());
+
+        send(&sink, heartbeat(0, "code")).await.unwrap();
+        server.wait_for_heartbeats(1).await;
+        assert_eq!(vec!["window".to_string()], server.buckets());
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn drops_heartbeats_of_rejected_buckets() {
+        let server = TestServer::start().await;
+        let sink = ServerSink::new(&server.server(), Arc::new(SystemClock::default())).unwrap();
+
+        server.fail_requests(4);
+        sink.create_bucket(&bucket("window")).await.unwrap();
+        sink.create_bucket(&bucket("afk")).await.unwrap();
+
+        server.fail_requests_with(Failure::Status("400 Bad Request"), 1);
+        send(&sink, heartbeat(0, "code")).await.unwrap();
+        sink.flush(Duration::from_secs(5)).await.unwrap();
+        assert_eq!(vec!["afk".to_string()], server.buckets());
+
+        let afk = SpooledHeartbeat {
+            bucket: "afk".to_string(),
+            ..heartbeat(1, "code")
+        };
+        send(&sink, afk).await.unwrap();
+        send(&sink, heartbeat(2, "firefox")).await.unwrap();
+        sink.flush(Duration::from_secs(5)).await.unwrap();
+
+        let heartbeats = server.wait_for_heartbeats(1).await;
+        assert_eq!(1, heartbeats.len());
+        assert_eq!("afk", heartbeats[0].bucket);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn reports_delivery_failures() {
+        let server = TestServer::start().await;
+        let sink = ServerSink::new(&server.server(), Arc::new(SystemClock::default())).unwrap();
+
+        server.fail_requests(4);
+        send(&sink, heartbeat(0, "code")).await.unwrap();
+        sink.flush(Duration::from_secs(5)).await.unwrap();
+
+        assert!(send(&sink, heartbeat(1, "firefox")).await.is_err());
+        sink.flush(Duration::from_secs(5)).await.unwrap();
+        assert!(send(&sink, heartbeat(2, "firefox")).await.is_ok());
+        assert_eq!(2, server.wait_for_heartbeats(2).await.len());
+    }
+
+    #[rstest]
+    #[case::server_error("503 Service Unavailable", "code")]
+    #[case::too_many_requests("429 Too Many Requests", "code")]
+    #[case::client_error("400 Bad Request", "firefox")]
+    #[case::unauthorized("401 Unauthorized", "firefox")]
+    #[tokio::test]
+    async fn classifies_error_statuses(#[case] status: &'static str, #[case] first_app: &str) {
+        let server = TestServer::start().await;
+        let sink = ServerSink::new(&server.server(), Arc::new(SystemClock::default())).unwrap();
+
+        server.fail_requests_with(Failure::Status(status), 1);
+        send(&sink, heartbeat(0, "code")).await.unwrap();
+        sink.flush(Duration::from_secs(5)).await.unwrap();
+
+        // Transient errors are retried, the rejected heartbeat is reported and dropped
+        let is_transient = first_app == "code";
+        assert_eq!(
+            is_transient,
+            send(&sink, heartbeat(1, "firefox")).await.is_ok()
+        );
+        sink.flush(Duration::from_secs(5)).await.unwrap();
+
+        let heartbeats = server.wait_for_heartbeats(1).await;
+        assert_eq!(if is_transient { 2 } else { 1 }, heartbeats.len());
+        assert_eq!(
+            Some(&Value::String(first_app.to_string())),
+            heartbeats[0].event.data.get("app")
+        );
+    }
+}
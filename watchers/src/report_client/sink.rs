@@ -0,0 +1,35 @@
+use async_trait::async_trait;
+use aw_client_rust::{Bucket, Event as AwEvent};
+use std::time::Duration;
+
+/// Destination of the buckets and heartbeats produced by the watchers.
+#[async_trait]
+pub trait EventSink: Send + Sync {
+    async fn create_bucket(&self, bucket: &Bucket) -> anyhow::Result<()>;
+
+    async fn heartbeat(
+        &self,
+        bucket_name: &str,
+        event: &AwEvent,
+        pulsetime: f64,
+    ) -> anyhow::Result<()>;
+
+    /// Waits until the heartbeats are delivered, which happens immediately for most sinks.
+    async fn flush(&self, _timeout: Duration) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+/// Drops all data, only the watchers' logs are left.
+pub struct NoSink;
+
+#[async_trait]
+impl EventSink for NoSink {
+    async fn create_bucket(&self, _: &Bucket) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    async fn heartbeat(&self, _: &str, _: &AwEvent, _: f64) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,304 @@
+use anyhow::Context;
+use aw_client_rust::Event as AwEvent;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::PathBuf;
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SpooledHeartbeat {
+    pub bucket: String,
+    pub pulsetime: f64,
+    pub event: AwEvent,
+}
+
+// About a week of window switches, the oldest heartbeats are dropped beyond it
+const MAX_HEARTBEATS: usize = 100_000;
+// The file is rewritten when it has that many more lines than the heartbeats
+const COMPACT_SLACK: usize = 1000;
+
+/// Heartbeats which could not be delivered to the server, persisted as JSON lines
+/// and replayed in the same order once the server is reachable again.
+///
+/// New and merged heartbeats are appended to the file, merging them again on load,
+/// and the file is rewritten only when it grows too much or after the replay.
+pub struct Spool {
+    path: PathBuf,
+    heartbeats: VecDeque<SpooledHeartbeat>,
+    max_heartbeats: usize,
+    is_full: bool,
+    // Lines in the file, including the outdated ones
+    lines: usize,
+}
+
+impl Spool {
+    pub fn load(path: PathBuf) -> anyhow::Result<Self> {
+        let mut spool = Self {
+            path,
+            heartbeats: VecDeque::new(),
+            max_heartbeats: MAX_HEARTBEATS,
+            is_full: false,
+            lines: 0,
+        };
+        let content = match fs::read_to_string(&spool.path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(spool),
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("Failed to read spool file {}", spool.path.display()))
+            }
+        };
+
+        for line in content.lines().filter(|line| !line.trim().is_empty()) {
+            spool.lines += 1;
+            match serde_json::from_str::<SpooledHeartbeat>(line) {
+                Ok(heartbeat) => {
+                    spool.add(heartbeat.bucket, &heartbeat.event, heartbeat.pulsetime);
+                }
+                Err(e) => warn!("Skipping malformed spooled heartbeat: {e}"),
+            }
+        }
+        if !spool.heartbeats.is_empty() {
+            info!(
+                "Loaded {} spooled heartbeats from {}",
+                spool.heartbeats.len(),
+                spool.path.display()
+            );
+        }
+
+        Ok(spool)
+    }
+
+    pub fn len(&self) -> usize {
+        self.heartbeats.len()
+    }
+
+    pub fn front(&self) -> Option<&SpooledHeartbeat> {
+        self.heartbeats.front()
+    }
+
+    /// Removes the heartbeat only in memory, `compact` updates the file after the replay.
+    pub fn pop_front(&mut self) {
+        self.heartbeats.pop_front();
+        self.is_full = false;
+    }
+
+    /// Adds the heartbeat to the queue merging it into the latest heartbeat of the same bucket
+    /// the way the server does it, so that a long outage doesn't produce an event per poll.
+    pub fn push(&mut self, bucket: &str, event: &AwEvent, pulsetime: f64) -> anyhow::Result<()> {
+        let heartbeat = self.add(bucket.to_string(), event, pulsetime);
+        let mut line = serde_json::to_string(heartbeat)?;
+        line.push('\n');
+
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut file| file.write_all(line.as_bytes()))
+            .with_context(|| format!("Failed to write to spool file {}", self.path.display()))?;
+        self.lines += 1;
+
+        if self.lines > self.heartbeats.len() * 2 + COMPACT_SLACK {
+            self.compact()?;
+        }
+        Ok(())
+    }
+
+    /// Rewrites the file with the current heartbeats, or removes it if there are none.
+    pub fn compact(&mut self) -> anyhow::Result<()> {
+        if self.heartbeats.is_empty() {
+            self.lines = 0;
+            return match fs::remove_file(&self.path) {
+                Err(e) if e.kind() != ErrorKind::NotFound => Err(e).with_context(|| {
+                    format!("Failed to remove spool file {}", self.path.display())
+                }),
+                _ => Ok(()),
+            };
+        }
+        if self.lines == self.heartbeats.len() {
+            return Ok(());
+        }
+
+        let mut content = String::new();
+        for heartbeat in &self.heartbeats {
+            content.push_str(&serde_json::to_string(heartbeat)?);
+            content.push('\n');
+        }
+
+        // Write a complete file first so that a crash doesn't leave a truncated spool
+        let temp_path = self.path.with_extension("tmp");
+        fs::write(&temp_path, content)
+            .with_context(|| format!("Failed to write spool file {}", temp_path.display()))?;
+        fs::rename(&temp_path, &self.path)
+            .with_context(|| format!("Failed to replace spool file {}", self.path.display()))?;
+        self.lines = self.heartbeats.len();
+        Ok(())
+    }
+
+    // Returns the added or the merged heartbeat
+    fn add(&mut self, bucket: String, event: &AwEvent, pulsetime: f64) -> &SpooledHeartbeat {
+        let last = self
+            .heartbeats
+            .iter()
+            .rposition(|heartbeat| heartbeat.bucket == bucket);
+        if let Some(last) = last {
+            if merge_heartbeat(&mut self.heartbeats[last], event, pulsetime) {
+                return &self.heartbeats[last];
+            }
+        }
+
+        if self.heartbeats.len() >= self.max_heartbeats {
+            if !self.is_full {
+                warn!(
+                    "Spool {} is full, dropping the oldest heartbeats",
+                    self.path.display()
+                );
+                self.is_full = true;
+            }
+            self.heartbeats.pop_front();
+        }
+        self.heartbeats.push_back(SpooledHeartbeat {
+            bucket,
+            pulsetime,
+            event: event.clone(),
+        });
+        self.heartbeats.back().unwrap()
+    }
+}
+
+// The same rules as in aw-server heartbeat: equal data within the pulse time extends the last event.
+pub(super) fn merge_heartbeat(
+    last: &mut SpooledHeartbeat,
+    event: &AwEvent,
+    pulsetime: f64,
+) -> bool {
+    if last.event.data != event.data {
+        return false;
+    }
+
+    let last_end = last.event.timestamp + last.event.duration;
+    let pulse_end = last_end + chrono::TimeDelta::milliseconds((pulsetime * 1000.0) as i64);
+    if event.timestamp < last.event.timestamp || event.timestamp > pulse_end {
+        return false;
+    }
+
+    let event_end = event.timestamp + event.duration;
+    last.event.duration = last_end.max(event_end) - last.event.timestamp;
+    last.pulsetime = last.pulsetime.max(pulsetime);
+
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeDelta, TimeZone, Utc};
+    use rstest::rstest;
+    use serde_json::{Map, Value};
+    use tempfile::tempdir;
+
+    fn event(seconds: i64, duration: i64, app: &str) -> AwEvent {
+        let mut data = Map::new();
+        data.insert("app".to_string(), Value::String(app.to_string()));
+
+        AwEvent {
+            id: None,
+            timestamp: Utc.with_ymd_and_hms(2024, 1, 1, 10, 0, 0).unwrap()
+                + TimeDelta::seconds(seconds),
+            duration: TimeDelta::seconds(duration),
+            data,
+        }
+    }
+
+    #[rstest]
+    fn merges_heartbeats() {
+        let dir = tempdir().unwrap();
+        let mut spool = Spool::load(dir.path().join("spool.jsonl")).unwrap();
+
+        spool.push("window", &event(0, 0, "code"), 2.0).unwrap();
+        spool.push("window", &event(1, 0, "code"), 2.0).unwrap();
+        spool.push("idle", &event(1, 0, "code"), 2.0).unwrap();
+        spool.push("window", &event(2, 0, "code"), 2.0).unwrap();
+        assert_eq!(2, spool.len());
+        assert_eq!(TimeDelta::seconds(2), spool.front().unwrap().event.duration);
+
+        // Too late for the pulse time
+        spool.push("window", &event(10, 0, "code"), 2.0).unwrap();
+        // Different data
+        spool.push("window", &event(11, 0, "firefox"), 2.0).unwrap();
+        assert_eq!(4, spool.len());
+    }
+
+    #[rstest]
+    fn persists_between_loads() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("awatcher").join("spool.jsonl");
+
+        let mut spool = Spool::load(path.clone()).unwrap();
+        spool.push("window", &event(0, 0, "code"), 2.0).unwrap();
+        spool.push("window", &event(1, 0, "firefox"), 2.0).unwrap();
+
+        let mut spool = Spool::load(path.clone()).unwrap();
+        assert_eq!(2, spool.len());
+        assert_eq!(
+            Some(&Value::String("code".to_string())),
+            spool.front().unwrap().event.data.get("app")
+        );
+
+        spool.pop_front();
+        spool.pop_front();
+        assert!(path.exists());
+        spool.compact().unwrap();
+        assert!(!path.exists());
+    }
+
+    #[rstest]
+    fn appends_merged_heartbeats() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("spool.jsonl");
+
+        let mut spool = Spool::load(path.clone()).unwrap();
+        for seconds in 0..3 {
+            spool
+                .push("window", &event(seconds, 0, "code"), 2.0)
+                .unwrap();
+        }
+        spool.push("window", &event(3, 0, "firefox"), 2.0).unwrap();
+        spool.pop_front();
+        assert_eq!(4, fs::read_to_string(&path).unwrap().lines().count());
+
+        // The popped heartbeat is still in the file until it's compacted
+        let spool = Spool::load(path.clone()).unwrap();
+        assert_eq!(2, spool.len());
+        assert_eq!(TimeDelta::seconds(2), spool.front().unwrap().event.duration);
+
+        let mut spool = Spool::load(path.clone()).unwrap();
+        spool.pop_front();
+        spool.compact().unwrap();
+        assert_eq!(1, fs::read_to_string(&path).unwrap().lines().count());
+        assert_eq!(1, Spool::load(path).unwrap().len());
+    }
+
+    #[rstest]
+    fn drops_oldest_heartbeats() {
+        let dir = tempdir().unwrap();
+        let mut spool = Spool::load(dir.path().join("spool.jsonl")).unwrap();
+        spool.max_heartbeats = 2;
+
+        for (seconds, app) in ["code", "firefox", "vim"].into_iter().enumerate() {
+            spool
+                .push("window", &event(seconds as i64, 0, app), 2.0)
+                .unwrap();
+        }
+
+        assert_eq!(2, spool.len());
+        assert_eq!(
+            Some(&Value::String("firefox".to_string())),
+            spool.front().unwrap().event.data.get("app")
+        );
+    }
+}
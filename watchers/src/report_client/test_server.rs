@@ -0,0 +1,188 @@
+/*
+ * In-process aw-server REST API for the tests, only the requests of ReportClient are served:
+ * POST /api/0/buckets/{id} and POST /api/0/buckets/{id}/heartbeat?pulsetime={pulsetime}.
+ * Failures either close the connection without a response, as an unavailable server does,
+ * or respond with an error status.
+ */
+use crate::config::{RetryPolicy, Server};
+use aw_client_rust::{Bucket, Event as AwEvent};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+
+#[derive(Clone, Debug)]
+pub struct Heartbeat {
+    pub bucket: String,
+    pub pulsetime: f64,
+    pub event: AwEvent,
+}
+
+#[derive(Clone, Copy)]
+pub enum Failure {
+    /// Closes the connection without a response.
+    Drop,
+    /// Responds with the status line, e.g. "503 Service Unavailable".
+    Status(&'static str),
+}
+
+#[derive(Default)]
+struct State {
+    buckets: Vec<Bucket>,
+    heartbeats: Vec<Heartbeat>,
+    failures: u32,
+    failure: Option<Failure>,
+}
+
+pub struct TestServer {
+    port: u16,
+    state: Arc<Mutex<State>>,
+    task: JoinHandle<()>,
+}
+
+impl TestServer {
+    pub async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let state = Arc::new(Mutex::new(State::default()));
+
+        let server_state = Arc::clone(&state);
+        let task = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(serve(stream, Arc::clone(&server_state)));
+            }
+        });
+
+        Self { port, state, task }
+    }
+
+    pub fn server(&self) -> Server {
+        Server {
+            port: self.port,
+            host: "127.0.0.1".to_string(),
+            api_key: None,
+            spool_file: None,
+            retry: RetryPolicy {
+                attempts: 3,
+                initial_backoff: Duration::from_millis(1),
+                max_backoff: Duration::from_millis(10),
+                jitter: 0.0,
+            },
+        }
+    }
+
+    /// Drops the connections of the next requests without a response.
+    pub fn fail_requests(&self, count: u32) {
+        self.fail_requests_with(Failure::Drop, count);
+    }
+
+    pub fn fail_requests_with(&self, failure: Failure, count: u32) {
+        let mut state = self.state.lock().unwrap();
+        state.failure = Some(failure);
+        state.failures = count;
+    }
+
+    pub fn buckets(&self) -> Vec<String> {
+        let state = self.state.lock().unwrap();
+        state
+            .buckets
+            .iter()
+            .map(|bucket| bucket.id.clone())
+            .collect()
+    }
+
+    /// Heartbeats are sent in the background, waits until there are at least `count` of them.
+    pub async fn wait_for_heartbeats(&self, count: usize) -> Vec<Heartbeat> {
+        for _ in 0..500 {
+            let heartbeats = self.state.lock().unwrap().heartbeats.clone();
+            if heartbeats.len() >= count {
+                return heartbeats;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("Expected {count} heartbeats");
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn serve(stream: TcpStream, state: Arc<Mutex<State>>) {
+    let mut stream = BufReader::new(stream);
+    loop {
+        let mut request_line = String::new();
+        if stream.read_line(&mut request_line).await.unwrap_or(0) == 0 {
+            return;
+        }
+        let mut content_length = 0;
+        loop {
+            let mut header = String::new();
+            stream.read_line(&mut header).await.unwrap();
+            let header = header.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                if name.eq_ignore_ascii_case("content-length") {
+                    content_length = value.trim().parse().unwrap();
+                }
+            }
+        }
+        let mut body = vec![0; content_length];
+        stream.read_exact(&mut body).await.unwrap();
+
+        let path = request_line.split_whitespace().nth(1).unwrap_or_default();
+        let status = {
+            let mut state = state.lock().unwrap();
+            let failure = state.failure.filter(|_| state.failures > 0);
+            if failure.is_some() {
+                state.failures -= 1;
+            }
+            match failure {
+                Some(Failure::Drop) => return,
+                Some(Failure::Status(status)) => status,
+                None => record(&mut state, path, &body),
+            }
+        };
+
+        let response = format!("HTTP/1.1 {status}\r\nContent-Length: 0\r\n\r\n");
+        stream
+            .get_mut()
+            .write_all(response.as_bytes())
+            .await
+            .unwrap();
+    }
+}
+
+fn record(state: &mut State, path: &str, body: &[u8]) -> &'static str {
+    let Some(path) = path.strip_prefix("/api/0/buckets/") else {
+        return "404 Not Found";
+    };
+    let (path, query) = path.split_once('?').unwrap_or((path, ""));
+
+    if let Some(bucket) = path.strip_suffix("/heartbeat") {
+        let pulsetime = query
+            .strip_prefix("pulsetime=")
+            .and_then(|pulsetime| pulsetime.parse().ok());
+        let (Some(pulsetime), Ok(event)) = (pulsetime, serde_json::from_slice(body)) else {
+            return "400 Bad Request";
+        };
+        state.heartbeats.push(Heartbeat {
+            bucket: bucket.to_string(),
+            pulsetime,
+            event,
+        });
+    } else {
+        let Ok(mut bucket) = serde_json::from_slice::<Bucket>(body) else {
+            return "400 Bad Request";
+        };
+        bucket.id = path.to_string();
+        state.buckets.push(bucket);
+    }
+
+    "200 OK"
+}
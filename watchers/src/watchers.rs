@@ -1,14 +1,26 @@
 #[cfg(feature = "gnome")]
 mod gnome_idle;
 #[cfg(feature = "gnome")]
+mod gnome_introspect_window;
+#[cfg(feature = "gnome")]
 mod gnome_wayland;
 #[cfg(feature = "gnome")]
 mod gnome_window;
+mod hyprland_window;
+mod i3_ipc_window;
 pub mod idle;
 #[cfg(feature = "kwin_window")]
 mod kwin_window;
+#[cfg(feature = "media")]
+pub mod media;
+mod niri_window;
+#[cfg(feature = "screen_lock")]
+pub mod screen_lock;
+#[cfg(feature = "suspend")]
+pub mod suspend;
 mod wl_connection;
 mod wl_cosmic_toplevel_management;
+mod wl_ext_foreign_toplevel_list;
 mod wl_ext_idle_notify;
 mod wl_foreign_toplevel_management;
 mod wl_kwin_idle;
@@ -17,15 +29,21 @@ mod x11_screensaver_idle;
 mod x11_window;
 
 use crate::{config::Config, report_client::ReportClient};
+use anyhow::anyhow;
 use async_trait::async_trait;
+use std::future::{pending, Future};
+use std::pin::Pin;
 use std::{fmt::Display, sync::Arc};
-use tokio::time::{sleep, timeout, Duration};
+use tokio::time::Duration;
 
 pub enum WatcherType {
     Idle,
     ActiveWindow,
 }
 
+/// Backend name which stands for the automatic selection.
+pub const AUTO_BACKEND: &str = "auto";
+
 impl WatcherType {
     fn sleep_time(&self, config: &Config) -> Duration {
         match self {
@@ -33,6 +51,13 @@ impl WatcherType {
             WatcherType::ActiveWindow => config.poll_time_window.to_std().unwrap(),
         }
     }
+
+    fn backend_names<'a>(&self, config: &'a Config) -> &'a [String] {
+        match self {
+            WatcherType::Idle => &config.idle_backends,
+            WatcherType::ActiveWindow => &config.window_backends,
+        }
+    }
 }
 
 impl Display for WatcherType {
@@ -51,108 +76,624 @@ pub trait Watcher: Send {
         Self: Sized;
 
     async fn run_iteration(&mut self, client: &Arc<ReportClient>) -> anyhow::Result<()>;
+
+    /// Resolves when the watched state may have changed to run the next iteration right away.
+    async fn wait_for_event(&mut self) -> anyhow::Result<()> {
+        pending().await
+    }
+
+    /// Event-driven watchers run iterations on changes, and otherwise only as a keep-alive.
+    fn is_event_driven(&self) -> bool {
+        false
+    }
+
+    /// Checks if the watcher can run without keeping it, the watchers with side effects or delays
+    /// in `new` check only what they need.
+    async fn probe(client: &Arc<ReportClient>) -> anyhow::Result<()>
+    where
+        Self: Sized + 'static,
+    {
+        stop_watcher(Box::new(Self::new(client).await?)).await;
+        Ok(())
+    }
 }
 
-async fn create_watcher<T: Watcher>(client: &Arc<ReportClient>, name: &str) -> Option<T> {
-    match T::new(client).await {
-        Ok(watcher) => {
-            info!("Selected watcher: {name}");
-            Some(watcher)
-        }
-        Err(e) => {
-            debug!("Watcher \"{name}\" cannot run: {e}");
-            None
-        }
+type WatcherFuture<'a> =
+    Pin<Box<dyn Future<Output = anyhow::Result<Box<dyn Watcher>>> + Send + 'a>>;
+type CreateWatcher = for<'a> fn(&'a Arc<ReportClient>) -> WatcherFuture<'a>;
+type ProbeFuture<'a> = Pin<Box<dyn Future<Output = anyhow::Result<()>> + Send + 'a>>;
+type ProbeWatcher = for<'a> fn(&'a Arc<ReportClient>) -> ProbeFuture<'a>;
+
+/// Watcher implementation which can be selected by its name in the config.
+pub struct Backend {
+    pub name: &'static str,
+    pub description: &'static str,
+    create: CreateWatcher,
+    probe: ProbeWatcher,
+}
+
+fn create_watcher<T: Watcher + 'static>(client: &Arc<ReportClient>) -> WatcherFuture<'_> {
+    Box::pin(async move {
+        let watcher: Box<dyn Watcher> = Box::new(T::new(client).await?);
+        Ok(watcher)
+    })
+}
+
+fn probe_watcher<T: Watcher + 'static>(client: &Arc<ReportClient>) -> ProbeFuture<'_> {
+    Box::pin(T::probe(client))
+}
+
+// The order is the priority of the automatic selection.
+const IDLE_BACKENDS: &[Backend] = &[
+    Backend {
+        name: "ext-idle-notify",
+        description: "Wayland idle (ext-idle-notify-v1)",
+        create: create_watcher::<wl_ext_idle_notify::IdleWatcher>,
+        probe: probe_watcher::<wl_ext_idle_notify::IdleWatcher>,
+    },
+    Backend {
+        name: "kwin-idle",
+        description: "Wayland idle (KDE)",
+        create: create_watcher::<wl_kwin_idle::IdleWatcher>,
+        probe: probe_watcher::<wl_kwin_idle::IdleWatcher>,
+    },
+    Backend {
+        name: "x11-screensaver",
+        description: "X11 idle (screensaver)",
+        create: create_watcher::<x11_screensaver_idle::IdleWatcher>,
+        probe: probe_watcher::<x11_screensaver_idle::IdleWatcher>,
+    },
+    #[cfg(feature = "gnome")]
+    Backend {
+        name: "gnome",
+        description: "Gnome idle (Mutter/IdleMonitor)",
+        create: create_watcher::<gnome_idle::IdleWatcher>,
+        probe: probe_watcher::<gnome_idle::IdleWatcher>,
+    },
+];
+
+const WINDOW_BACKENDS: &[Backend] = &[
+    // Fails fast without HYPRLAND_INSTANCE_SIGNATURE, and reports more than wlr-foreign-toplevel
+    Backend {
+        name: "hyprland",
+        description: "Hyprland window (IPC)",
+        create: create_watcher::<hyprland_window::WindowWatcher>,
+        probe: probe_watcher::<hyprland_window::WindowWatcher>,
+    },
+    // Fails fast without SWAYSOCK or I3SOCK, also covers i3 in X11
+    Backend {
+        name: "i3-ipc",
+        description: "Sway/i3 window (i3 IPC)",
+        create: create_watcher::<i3_ipc_window::WindowWatcher>,
+        probe: probe_watcher::<i3_ipc_window::WindowWatcher>,
+    },
+    // Fails fast without NIRI_SOCKET
+    Backend {
+        name: "niri",
+        description: "niri window (IPC event stream)",
+        create: create_watcher::<niri_window::WindowWatcher>,
+        probe: probe_watcher::<niri_window::WindowWatcher>,
+    },
+    Backend {
+        name: "wlr-foreign-toplevel",
+        description: "Wayland window (wlr-foreign-toplevel-management-unstable-v1)",
+        create: create_watcher::<wl_foreign_toplevel_management::WindowWatcher>,
+        probe: probe_watcher::<wl_foreign_toplevel_management::WindowWatcher>,
+    },
+    Backend {
+        name: "cosmic-toplevel",
+        description: "Cosmic Wayland window (cosmic-toplevel-info-unstable-v1)",
+        create: create_watcher::<wl_cosmic_toplevel_management::WindowWatcher>,
+        probe: probe_watcher::<wl_cosmic_toplevel_management::WindowWatcher>,
+    },
+    // XWayland gives _NET_WM_NAME on some windows in KDE, but not on others
+    #[cfg(feature = "kwin_window")]
+    Backend {
+        name: "kwin",
+        description: "KWin window (script)",
+        create: create_watcher::<kwin_window::WindowWatcher>,
+        probe: probe_watcher::<kwin_window::WindowWatcher>,
+    },
+    // Only after kwin which reports more on KDE, with wlr it's reached only when configured
+    Backend {
+        name: "ext-foreign-toplevel",
+        description: "Wayland window (ext-foreign-toplevel-list-v1 with wlr-foreign-toplevel or Plasma window management)",
+        create: create_watcher::<wl_ext_foreign_toplevel_list::WindowWatcher>,
+        probe: probe_watcher::<wl_ext_foreign_toplevel_list::WindowWatcher>,
+    },
+    #[cfg(feature = "gnome")]
+    Backend {
+        name: "gnome",
+        description: "Gnome window (extension)",
+        create: create_watcher::<gnome_window::WindowWatcher>,
+        probe: probe_watcher::<gnome_window::WindowWatcher>,
+    },
+    #[cfg(feature = "gnome")]
+    Backend {
+        name: "gnome-introspect",
+        description: "Gnome window (Shell Introspect)",
+        create: create_watcher::<gnome_introspect_window::WindowWatcher>,
+        probe: probe_watcher::<gnome_introspect_window::WindowWatcher>,
+    },
+    Backend {
+        name: "x11",
+        description: "X11 window",
+        create: create_watcher::<x11_window::WindowWatcher>,
+        probe: probe_watcher::<x11_window::WindowWatcher>,
+    },
+];
+
+/// Compiled-in backends in the order of the automatic selection.
+pub fn backends(watcher_type: &WatcherType) -> &'static [Backend] {
+    match watcher_type {
+        WatcherType::Idle => IDLE_BACKENDS,
+        WatcherType::ActiveWindow => WINDOW_BACKENDS,
     }
 }
 
-macro_rules! watch {
-    ($watcher:expr) => {
-        if let Some(watcher) = $watcher.await {
-            let watcher: Box<dyn Watcher> = Box::new(watcher);
-            return Some(watcher);
+// "auto" in the list stands for all backends which are not listed explicitly.
+fn select_backends(
+    watcher_type: &WatcherType,
+    names: &[String],
+) -> anyhow::Result<Vec<&'static Backend>> {
+    if names.is_empty() {
+        anyhow::bail!("No {watcher_type} backends are configured");
+    }
+    let all = backends(watcher_type);
+    let is_listed = |backend: &Backend| names.iter().any(|name| name == backend.name);
+
+    let mut selected: Vec<&Backend> = Vec::new();
+    for name in names {
+        let candidates: Vec<&Backend> = if name == AUTO_BACKEND {
+            all.iter().filter(|backend| !is_listed(backend)).collect()
+        } else {
+            let backend = all.iter().find(|b| b.name == name).ok_or_else(|| {
+                let known: Vec<_> = all.iter().map(|b| b.name).collect();
+                anyhow!(
+                    "Unknown {watcher_type} backend \"{name}\", expected \"{AUTO_BACKEND}\" or one of: {}",
+                    known.join(", ")
+                )
+            })?;
+            vec![backend]
+        };
+        for backend in candidates {
+            if !selected.iter().any(|b| b.name == backend.name) {
+                selected.push(backend);
+            }
         }
-    };
+    }
+
+    Ok(selected)
 }
 
 async fn filter_first_supported(
     client: &Arc<ReportClient>,
     watcher_type: &WatcherType,
-) -> Option<Box<dyn Watcher>> {
-    match watcher_type {
-        WatcherType::Idle => {
-            watch!(create_watcher::<wl_ext_idle_notify::IdleWatcher>(
-                client,
-                "Wayland idle (ext-idle-notify-v1)"
-            ));
-            watch!(create_watcher::<wl_kwin_idle::IdleWatcher>(
-                client,
-                "Wayland idle (KDE)"
-            ));
-            watch!(create_watcher::<x11_screensaver_idle::IdleWatcher>(
-                client,
-                "X11 idle (screensaver)"
-            ));
-            #[cfg(feature = "gnome")]
-            watch!(create_watcher::<gnome_idle::IdleWatcher>(
-                client,
-                "Gnome idle (Mutter/IdleMonitor)"
-            ));
-        }
-        WatcherType::ActiveWindow => {
-            watch!(create_watcher::<
-                wl_foreign_toplevel_management::WindowWatcher,
-            >(
-                client,
-                "Wayland window (wlr-foreign-toplevel-management-unstable-v1)"
-            ));
-            watch!(
-                create_watcher::<wl_cosmic_toplevel_management::WindowWatcher>(
-                    client,
-                    "Cosmic Wayland window (cosmic-toplevel-info-unstable-v1)"
-                )
-            );
-            // XWayland gives _NET_WM_NAME on some windows in KDE, but not on others
-            #[cfg(feature = "kwin_window")]
-            watch!(create_watcher::<kwin_window::WindowWatcher>(
-                client,
-                "KWin window (script)"
-            ));
-            #[cfg(feature = "gnome")]
-            watch!(create_watcher::<gnome_window::WindowWatcher>(
-                client,
-                "Gnome window (extension)"
-            ));
-            watch!(create_watcher::<x11_window::WindowWatcher>(
-                client,
-                "X11 window"
-            ));
+) -> anyhow::Result<Box<dyn Watcher>> {
+    let names = watcher_type.backend_names(&client.config);
+    let mut failures = Vec::new();
+    for backend in select_backends(watcher_type, names)? {
+        match (backend.create)(client).await {
+            Ok(watcher) => {
+                info!("Selected watcher: {}", backend.description);
+                return Ok(watcher);
+            }
+            Err(e) => {
+                debug!("Watcher \"{}\" cannot run: {e}", backend.description);
+                failures.push(format!("{}: {e}", backend.name));
+            }
         }
-    };
+    }
+
+    if names.iter().any(|name| name == AUTO_BACKEND) {
+        Err(anyhow!("No supported {watcher_type} watcher is found"))
+    } else {
+        Err(anyhow!(
+            "None of the configured {watcher_type} backends can start:\n{}",
+            failures.join("\n")
+        ))
+    }
+}
+
+/// Outcome of starting a backend.
+pub struct Probe {
+    pub backend: &'static Backend,
+    pub error: Option<String>,
+    /// The backend which is selected to run with the current config.
+    pub is_selected: bool,
+}
+
+/// Checks every compiled-in backend without running it.
+pub async fn probe_backends(
+    client: &Arc<ReportClient>,
+    watcher_type: &WatcherType,
+) -> anyhow::Result<Vec<Probe>> {
+    let mut probes = Vec::new();
+    for backend in backends(watcher_type) {
+        let error = (backend.probe)(client)
+            .await
+            .err()
+            .map(|e| format!("{e:#}"));
+        probes.push(Probe {
+            backend,
+            error,
+            is_selected: false,
+        });
+    }
+
+    let names = watcher_type.backend_names(&client.config);
+    let selected = select_backends(watcher_type, names)?
+        .iter()
+        .find_map(|backend| {
+            probes
+                .iter()
+                .position(|probe| probe.backend.name == backend.name && probe.error.is_none())
+        });
+    if let Some(selected) = selected {
+        probes[selected].is_selected = true;
+    }
+
+    Ok(probes)
+}
 
-    None
+// Some watchers clean up with blocking calls on drop
+async fn stop_watcher(watcher: Box<dyn Watcher>) {
+    if let Err(e) = tokio::task::spawn_blocking(move || drop(watcher)).await {
+        error!("Failed to stop the watcher: {e}");
+    }
 }
 
-pub async fn run_first_supported(client: Arc<ReportClient>, watcher_type: &WatcherType) -> bool {
-    let supported_watcher = filter_first_supported(&client, watcher_type).await;
-    if let Some(mut watcher) = supported_watcher {
+// Iterations failing in a row before the watcher is considered broken and selected again
+const MAX_CONSECUTIVE_FAILURES: u32 = 5;
+const MAX_RESELECTION_BACKOFF: Duration = Duration::from_secs(300);
+
+pub async fn run_first_supported(
+    client: Arc<ReportClient>,
+    watcher_type: &WatcherType,
+) -> anyhow::Result<()> {
+    let mut watcher = filter_first_supported(&client, watcher_type).await?;
+
+    let initial_backoff = watcher_type.sleep_time(&client.config);
+    let mut backoff = initial_backoff;
+    loop {
+        if run_until_broken(&client, watcher_type, watcher.as_mut()).await {
+            backoff = initial_backoff;
+        }
+        warn!(
+            "{watcher_type} watcher failed {MAX_CONSECUTIVE_FAILURES} times in a row, selecting it again"
+        );
+        stop_watcher(watcher).await;
+
+        watcher = loop {
+            client.clock.sleep(backoff).await;
+            backoff = (backoff * 2).min(MAX_RESELECTION_BACKOFF);
+
+            match filter_first_supported(&client, watcher_type).await {
+                Ok(watcher) => break watcher,
+                Err(e) => warn!("{e}, retrying in {backoff:?}"),
+            }
+        };
+    }
+}
+
+// Runs the watcher until it fails too many times in a row, returns if any iteration succeeded.
+async fn run_until_broken(
+    client: &Arc<ReportClient>,
+    watcher_type: &WatcherType,
+    watcher: &mut dyn Watcher,
+) -> bool {
+    let sleep_time = watcher_type.sleep_time(&client.config);
+    let interval = if watcher.is_event_driven() {
+        info!("Starting event-driven {watcher_type} watcher");
+        client.config.keep_alive.to_std().unwrap()
+    } else {
         info!("Starting {watcher_type} watcher");
-        loop {
-            let sleep_time = watcher_type.sleep_time(&client.config);
+        sleep_time
+    };
+    client.set_heartbeat_interval(watcher_type, interval);
 
-            match timeout(sleep_time, watcher.run_iteration(&client)).await {
-                Ok(Ok(())) => { /* Successfully completed. */ }
-                Ok(Err(e)) => {
-                    error!("Error on {watcher_type} iteration: {e}");
-                }
-                Err(_) => {
-                    error!("Timeout on {watcher_type} iteration after {sleep_time:?}");
+    let mut has_succeeded = false;
+    let mut consecutive_failures = 0;
+    loop {
+        let result = tokio::select! {
+            result = watcher.run_iteration(client) => Some(result),
+            () = client.clock.sleep(sleep_time) => None,
+        };
+        match result {
+            Some(Ok(())) => {
+                has_succeeded = true;
+                consecutive_failures = 0;
+            }
+            Some(Err(e)) => {
+                error!("Error on {watcher_type} iteration: {e}");
+                consecutive_failures += 1;
+            }
+            None => {
+                error!("Timeout on {watcher_type} iteration after {sleep_time:?}");
+                consecutive_failures += 1;
+            }
+        }
+        if consecutive_failures >= MAX_CONSECUTIVE_FAILURES {
+            return has_succeeded;
+        }
+
+        tokio::select! {
+            () = client.clock.sleep(interval) => {}
+            result = watcher.wait_for_event() => {
+                if let Err(e) = result {
+                    error!("Error on waiting for {watcher_type} events: {e}");
+                    consecutive_failures += 1;
+                    client.clock.sleep(sleep_time).await;
                 }
             }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{Clock, SimulatedClock};
+    use crate::config::{MediaIdle, MediaPlayers, Sink};
+    use chrono::{DateTime, TimeDelta, TimeZone, Utc};
+    use rstest::rstest;
+    use serde_json::Value;
+    use tempfile::NamedTempFile;
+
+    struct FailingWatcher {
+        successes: u32,
+        iterations: u32,
+    }
+
+    #[async_trait]
+    impl Watcher for FailingWatcher {
+        async fn new(_: &Arc<ReportClient>) -> anyhow::Result<Self> {
+            Ok(Self {
+                successes: 0,
+                iterations: 0,
+            })
+        }
+
+        async fn run_iteration(&mut self, _: &Arc<ReportClient>) -> anyhow::Result<()> {
+            self.iterations += 1;
+            if self.iterations <= self.successes {
+                Ok(())
+            } else {
+                Err(anyhow!("Broken"))
+            }
+        }
+    }
+
+    // Reports idle by the input times of the script until it ends
+    struct ScriptedIdleWatcher {
+        idle_state: idle::Tracker,
+        inputs: Vec<DateTime<Utc>>,
+        end: DateTime<Utc>,
+    }
+
+    impl ScriptedIdleWatcher {
+        fn with_script(
+            client: &ReportClient,
+            inputs: Vec<DateTime<Utc>>,
+            end: DateTime<Utc>,
+        ) -> Self {
+            Self {
+                idle_state: idle::Tracker::new(client.clock.now(), client.config.idle_timeout),
+                inputs,
+                end,
+            }
+        }
+    }
+
+    #[async_trait]
+    impl Watcher for ScriptedIdleWatcher {
+        async fn new(_: &Arc<ReportClient>) -> anyhow::Result<Self> {
+            Err(anyhow!("Scripted"))
+        }
+
+        async fn run_iteration(&mut self, client: &Arc<ReportClient>) -> anyhow::Result<()> {
+            let now = client.clock.now();
+            if now >= self.end {
+                return Err(anyhow!("Script ended"));
+            }
+            let last_input = self.inputs.iter().rev().find(|input| **input <= now);
+            let seconds = last_input.map_or(0, |input| (now - *input).num_seconds());
+
+            let status = self
+                .idle_state
+                .get_with_last_input(now, seconds.try_into()?)?;
+            client.handle_idle_status(status).await
+        }
+    }
+
+    fn config(sink: Sink) -> Config {
+        Config {
+            servers: Vec::new(),
+            idle_timeout: TimeDelta::seconds(180),
+            idle_timeouts: Vec::new(),
+            poll_time_idle: TimeDelta::zero(),
+            poll_time_window: TimeDelta::zero(),
+            keep_alive: TimeDelta::zero(),
+            idle_backends: vec![AUTO_BACKEND.to_string()],
+            window_backends: vec![AUTO_BACKEND.to_string()],
+            sink,
+            hostname: "host".to_string(),
+            idle_bucket_name: "afk".to_string(),
+            active_window_bucket_name: "window".to_string(),
+            process_info: false,
+            screen_lock: false,
+            mark_locked: false,
+            media_idle: MediaIdle::Off,
+            media_players: MediaPlayers::default(),
+            filters: Vec::new(),
+        }
+    }
+
+    async fn client() -> Arc<ReportClient> {
+        Arc::new(ReportClient::new(config(Sink::None)).await.unwrap())
+    }
+
+    fn start() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 1, 1, 10, 0, 0).unwrap()
+    }
 
-            sleep(sleep_time).await;
+    async fn simulated_client(file: &NamedTempFile) -> (Arc<ReportClient>, Arc<SimulatedClock>) {
+        let mut config = config(Sink::Jsonl(file.path().to_path_buf()));
+        config.poll_time_idle = TimeDelta::seconds(5);
+        let clock = Arc::new(SimulatedClock::new(start()));
+        let client = ReportClient::with_clock(config, Arc::clone(&clock) as Arc<dyn Clock>)
+            .await
+            .unwrap();
+
+        (Arc::new(client), clock)
+    }
+
+    // Status, seconds since the start and duration of the idle heartbeats
+    fn idle_heartbeats(file: &NamedTempFile) -> Vec<(String, i64, f64)> {
+        std::fs::read_to_string(file.path())
+            .unwrap()
+            .lines()
+            .filter_map(|line| {
+                let line: Value = serde_json::from_str(line).unwrap();
+                let event = line.get("heartbeat")?.get("event")?;
+                let timestamp: DateTime<Utc> = event["timestamp"].as_str()?.parse().unwrap();
+                Some((
+                    event["data"]["status"].as_str()?.to_string(),
+                    (timestamp - start()).num_seconds(),
+                    event["duration"].as_f64()?,
+                ))
+            })
+            .collect()
+    }
+
+    // Statuses in a row are merged into one span on the server
+    fn spans(heartbeats: &[(String, i64, f64)]) -> Vec<&str> {
+        let mut spans: Vec<&str> = Vec::new();
+        for (status, ..) in heartbeats {
+            if spans.last() != Some(&status.as_str()) {
+                spans.push(status);
+            }
         }
+        spans
+    }
+
+    #[rstest]
+    #[tokio::test(start_paused = true)]
+    async fn reports_long_idle() {
+        let file = NamedTempFile::new().unwrap();
+        let (client, _) = simulated_client(&file).await;
+        let inputs = vec![start(), start() + TimeDelta::seconds(60)];
+        let mut watcher =
+            ScriptedIdleWatcher::with_script(&client, inputs, start() + TimeDelta::hours(5));
+
+        run_until_broken(&client, &WatcherType::Idle, &mut watcher).await;
+
+        let heartbeats = idle_heartbeats(&file);
+        assert_eq!(vec!["not-afk", "afk"], spans(&heartbeats));
+        let (_, idle_start, duration) = heartbeats.last().unwrap();
+        assert_eq!(60, *idle_start);
+        assert!(*duration >= (TimeDelta::hours(5) - TimeDelta::seconds(65)).num_seconds() as f64);
+    }
+
+    #[rstest]
+    #[tokio::test(start_paused = true)]
+    async fn reports_suspend_by_clock_jump() {
+        let file = NamedTempFile::new().unwrap();
+        let (client, clock) = simulated_client(&file).await;
+        let end = start() + TimeDelta::hours(3);
+        let inputs = (0..180).map(|i| start() + TimeDelta::minutes(i)).collect();
+        let mut watcher = ScriptedIdleWatcher::with_script(&client, inputs, end);
+
+        let suspend = async {
+            clock.sleep(Duration::from_secs(30 * 60)).await;
+            clock.suspend(Duration::from_secs(3600));
+        };
+        tokio::join!(
+            run_until_broken(&client, &WatcherType::Idle, &mut watcher),
+            suspend
+        );
+
+        let heartbeats = idle_heartbeats(&file);
+        assert_eq!(vec!["not-afk", "afk", "not-afk"], spans(&heartbeats));
+        let (_, suspended_at, duration) = heartbeats
+            .iter()
+            .rev()
+            .find(|(status, ..)| status == "afk")
+            .unwrap();
+        assert!((1800..1810).contains(suspended_at));
+        assert!((3600.0..3610.0).contains(duration));
     }
 
-    false
+    #[rstest]
+    #[tokio::test(start_paused = true)]
+    async fn continues_idle_after_restart() {
+        let file = NamedTempFile::new().unwrap();
+        let (client, clock) = simulated_client(&file).await;
+        let inputs = vec![start()];
+
+        let end = start() + TimeDelta::minutes(10);
+        let mut watcher = ScriptedIdleWatcher::with_script(&client, inputs.clone(), end);
+        run_until_broken(&client, &WatcherType::Idle, &mut watcher).await;
+        clock.advance(Duration::from_secs(60));
+
+        // A new watcher doesn't know the state reported before
+        let end = start() + TimeDelta::minutes(30);
+        let mut watcher = ScriptedIdleWatcher::with_script(&client, inputs, end);
+        run_until_broken(&client, &WatcherType::Idle, &mut watcher).await;
+
+        let heartbeats = idle_heartbeats(&file);
+        assert_eq!(vec!["not-afk", "afk"], spans(&heartbeats));
+        assert!(heartbeats
+            .iter()
+            .filter(|(status, ..)| status == "afk")
+            .all(|(_, idle_start, _)| *idle_start == 0));
+    }
+
+    #[rstest]
+    #[case(0, false)]
+    #[case(3, true)]
+    #[tokio::test]
+    async fn stops_after_consecutive_failures(#[case] successes: u32, #[case] has_succeeded: bool) {
+        let client = client().await;
+        let mut watcher = FailingWatcher {
+            successes,
+            iterations: 0,
+        };
+
+        let result = run_until_broken(&client, &WatcherType::ActiveWindow, &mut watcher).await;
+
+        assert_eq!(has_succeeded, result);
+        assert_eq!(successes + MAX_CONSECUTIVE_FAILURES, watcher.iterations);
+    }
+
+    fn names(backends: &[&Backend]) -> Vec<&'static str> {
+        backends.iter().map(|backend| backend.name).collect()
+    }
+
+    #[rstest]
+    fn selects_backends() {
+        let all: Vec<_> = WINDOW_BACKENDS.iter().map(|b| b.name).collect();
+        let to_strings = |names: &[&str]| names.iter().map(ToString::to_string).collect::<Vec<_>>();
+
+        let selected = select_backends(&WatcherType::ActiveWindow, &to_strings(&["auto"])).unwrap();
+        assert_eq!(all, names(&selected));
+
+        let selected = select_backends(&WatcherType::ActiveWindow, &to_strings(&["x11"])).unwrap();
+        assert_eq!(vec!["x11"], names(&selected));
+
+        let selected =
+            select_backends(&WatcherType::ActiveWindow, &to_strings(&["x11", "auto"])).unwrap();
+        assert_eq!("x11", names(&selected)[0]);
+        assert_eq!(all.len(), selected.len());
+
+        let selected =
+            select_backends(&WatcherType::ActiveWindow, &to_strings(&["auto", "x11"])).unwrap();
+        assert_eq!(Some(&"x11"), names(&selected).last());
+        assert_eq!(all.len(), selected.len());
+
+        let error = select_backends(&WatcherType::Idle, &to_strings(&["x11"]))
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("Unknown idle backend \"x11\""));
+    }
 }
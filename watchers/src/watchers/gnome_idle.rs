@@ -1,53 +1,148 @@
+/*
+ * Mutter fires the idle watch when there is no input for the timeout, and the user active watch
+ * on the next input. The user active watch is removed after firing, so it's added on each idle.
+ * GetIdletime is polled if the watches cannot be added.
+ */
 use super::{gnome_wayland::load_watcher, idle, Watcher};
+use crate::clock::Clock;
 use crate::report_client::ReportClient;
-use anyhow::Context;
+use anyhow::{anyhow, Context};
 use async_trait::async_trait;
-use chrono::Utc;
+use chrono::TimeDelta;
+use futures_util::StreamExt;
+use std::future::pending;
 use std::sync::Arc;
-use zbus::Connection;
+use zbus::proxy::SignalStream;
+use zbus::{Connection, Proxy};
+
+struct Watches {
+    signals: SignalStream<'static>,
+    idle_watch: u32,
+    active_watch: Option<u32>,
+    is_idle: bool,
+}
 
 pub struct IdleWatcher {
-    dbus_connection: Connection,
+    idle_monitor: Proxy<'static>,
     idle_state: idle::Tracker,
+    watches: Option<Watches>,
+    clock: Arc<dyn Clock>,
 }
 
 impl IdleWatcher {
-    async fn seconds_since_input(&mut self) -> anyhow::Result<u32> {
-        let ms: u64 = self
-            .dbus_connection
-            .call_method(
-                Some("org.gnome.Mutter.IdleMonitor"),
-                "/org/gnome/Mutter/IdleMonitor/Core",
-                Some("org.gnome.Mutter.IdleMonitor"),
-                "GetIdletime",
-                &(),
-            )
-            .await?
-            .body()
-            .deserialize()?;
+    async fn connect(idle_timeout: TimeDelta, clock: Arc<dyn Clock>) -> anyhow::Result<Self> {
+        let connection = Connection::session().await?;
+        let idle_monitor = Proxy::new(
+            &connection,
+            "org.gnome.Mutter.IdleMonitor",
+            "/org/gnome/Mutter/IdleMonitor/Core",
+            "org.gnome.Mutter.IdleMonitor",
+        )
+        .await?;
+        let watcher = Self {
+            idle_monitor,
+            idle_state: idle::Tracker::new(clock.now(), idle_timeout),
+            watches: None,
+            clock,
+        };
+        watcher.seconds_since_input().await?;
+
+        Ok(watcher)
+    }
+
+    async fn seconds_since_input(&self) -> anyhow::Result<u32> {
+        let ms: u64 = self.idle_monitor.call("GetIdletime", &()).await?;
         u32::try_from(ms / 1000).with_context(|| format!("Number {ms} is invalid"))
     }
+
+    async fn add_watches(&mut self, idle_timeout: TimeDelta) -> anyhow::Result<()> {
+        // Subscribed before adding the watch to not miss it firing
+        let signals = self.idle_monitor.receive_signal("WatchFired").await?;
+        let interval = u64::try_from(idle_timeout.num_milliseconds())?;
+        let idle_watch: u32 = self.idle_monitor.call("AddIdleWatch", &interval).await?;
+
+        // The idle watch fires only when the timeout passes, not if it has passed already
+        let is_idle = i64::from(self.seconds_since_input().await?) >= idle_timeout.num_seconds();
+        if is_idle {
+            self.idle_state.mark_idle(self.clock.now());
+        }
+        self.watches = Some(Watches {
+            signals,
+            idle_watch,
+            active_watch: None,
+            is_idle,
+        });
+
+        Ok(())
+    }
 }
 
 #[async_trait]
 impl Watcher for IdleWatcher {
     async fn new(client: &Arc<ReportClient>) -> anyhow::Result<Self> {
-        let duration = client.config.idle_timeout;
-        load_watcher(|| async move {
-            let mut watcher = Self {
-                dbus_connection: Connection::session().await?,
-                idle_state: idle::Tracker::new(Utc::now(), duration),
-            };
-            watcher.seconds_since_input().await?;
-            Ok(watcher)
-        })
-        .await
+        let idle_timeout = client.config.shortest_idle_timeout();
+        let mut watcher =
+            load_watcher(|| Self::connect(idle_timeout, Arc::clone(&client.clock))).await?;
+        if let Err(e) = watcher.add_watches(idle_timeout).await {
+            warn!("Failed to add Mutter idle watches, polling the idle time instead: {e}");
+        }
+
+        Ok(watcher)
+    }
+
+    // Neither waits for GNOME Shell to start nor adds the idle watches
+    async fn probe(client: &Arc<ReportClient>) -> anyhow::Result<()> {
+        let idle_timeout = client.config.shortest_idle_timeout();
+        Self::connect(idle_timeout, Arc::clone(&client.clock))
+            .await
+            .map(drop)
     }
 
     async fn run_iteration(&mut self, client: &Arc<ReportClient>) -> anyhow::Result<()> {
-        let seconds = self.seconds_since_input().await?;
-        client
-            .handle_idle_status(self.idle_state.get_with_last_input(Utc::now(), seconds)?)
+        self.idle_state.set_window_timeout(client.idle_timeout());
+        let status = if let Some(watches) = &mut self.watches {
+            // Added here rather than on the event as waiting for events may be cancelled
+            if watches.is_idle && watches.active_watch.is_none() {
+                let active_watch = self.idle_monitor.call("AddUserActiveWatch", &()).await?;
+                watches.active_watch = Some(active_watch);
+            }
+            self.idle_state.get_reactive(self.clock.now())?
+        } else {
+            let seconds = self.seconds_since_input().await?;
+            self.idle_state
+                .get_with_last_input(self.clock.now(), seconds)?
+        };
+
+        client.handle_idle_status(status).await
+    }
+
+    async fn wait_for_event(&mut self) -> anyhow::Result<()> {
+        let Some(watches) = &mut self.watches else {
+            return pending().await;
+        };
+
+        let message = watches
+            .signals
+            .next()
             .await
+            .ok_or_else(|| anyhow!("Mutter idle monitor signals are closed"))?;
+        let id: u32 = message.body().deserialize()?;
+
+        if id == watches.idle_watch && !watches.is_idle {
+            watches.is_idle = true;
+            self.idle_state.mark_idle(self.clock.now());
+        } else if watches.active_watch == Some(id) {
+            watches.is_idle = false;
+            watches.active_watch = None;
+            self.idle_state.mark_not_idle(self.clock.now());
+        } else {
+            trace!("Ignoring Mutter idle watch {id}");
+        }
+
+        Ok(())
+    }
+
+    fn is_event_driven(&self) -> bool {
+        self.watches.is_some()
     }
 }
@@ -0,0 +1,193 @@
+/*
+ * GNOME Shell gives the windows over org.gnome.Shell.Introspect without any extension,
+ * but only to allowlisted senders (xdg-desktop-portal), or to everyone in the unsafe mode.
+ */
+use super::{gnome_wayland::load_watcher, Watcher};
+use crate::report_client::ReportClient;
+use anyhow::Context;
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::Arc;
+use zbus::zvariant::OwnedValue;
+use zbus::Connection;
+
+const ACCESS_DENIED: &str = "org.freedesktop.DBus.Error.AccessDenied";
+
+type Windows = HashMap<u64, HashMap<String, OwnedValue>>;
+
+fn is_access_denied(error: &zbus::Error) -> bool {
+    matches!(error, zbus::Error::MethodError(name, _, _) if name.as_str() == ACCESS_DENIED)
+}
+
+struct WindowData {
+    app_id: String,
+    title: String,
+    extra: HashMap<String, String>,
+}
+
+fn string_property(properties: &HashMap<String, OwnedValue>, name: &str) -> String {
+    properties
+        .get(name)
+        .and_then(|value| <&str>::try_from(value).ok())
+        .unwrap_or_default()
+        .to_string()
+}
+
+fn focused_window(windows: &Windows) -> Option<WindowData> {
+    let (id, properties) = windows.iter().find(|(_, properties)| {
+        properties
+            .get("has-focus")
+            .and_then(|value| bool::try_from(value).ok())
+            .unwrap_or(false)
+    })?;
+
+    let wm_class = string_property(properties, "wm-class");
+    let app_id = string_property(properties, "app-id");
+    let sandboxed_app_id = string_property(properties, "sandboxed-app-id");
+
+    let desktop_file_name = app_id.strip_suffix(".desktop").unwrap_or(&app_id);
+
+    let mut extra = HashMap::from([("window_id".to_string(), id.to_string())]);
+    if !desktop_file_name.is_empty() {
+        extra.insert(
+            "desktop_file_name".to_string(),
+            desktop_file_name.to_string(),
+        );
+    }
+    if !sandboxed_app_id.is_empty() {
+        extra.insert("sandboxed_app_id".to_string(), sandboxed_app_id);
+    }
+
+    Some(WindowData {
+        // WM_CLASS is reported by the extension and X11, the desktop file name is only a fallback
+        app_id: if wm_class.is_empty() {
+            desktop_file_name.to_string()
+        } else {
+            wm_class
+        },
+        title: string_property(properties, "title"),
+        extra,
+    })
+}
+
+pub struct WindowWatcher {
+    dbus_connection: Connection,
+}
+
+impl WindowWatcher {
+    async fn get_windows(&self) -> anyhow::Result<Windows> {
+        let reply = self
+            .dbus_connection
+            .call_method(
+                Some("org.gnome.Shell.Introspect"),
+                "/org/gnome/Shell/Introspect",
+                Some("org.gnome.Shell.Introspect"),
+                "GetWindows",
+                &(),
+            )
+            .await;
+
+        match reply {
+            Ok(reply) => reply
+                .body()
+                .deserialize()
+                .with_context(|| "GetWindows returned unexpected windows"),
+            Err(e) if is_access_denied(&e) => Err(anyhow::Error::from(e).context(
+                "Access to org.gnome.Shell.Introspect is denied, GNOME Shell allows it only \
+                in the unsafe mode (global.context.unsafe_mode = true in Looking Glass), \
+                install the Focused Window D-Bus extension instead",
+            )),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn connect() -> anyhow::Result<Self> {
+        let watcher = Self {
+            dbus_connection: Connection::session().await?,
+        };
+        watcher.get_windows().await?;
+
+        Ok(watcher)
+    }
+}
+
+#[async_trait]
+impl Watcher for WindowWatcher {
+    async fn new(_: &Arc<ReportClient>) -> anyhow::Result<Self> {
+        // Waiting for GNOME Shell to start is useless if the access is denied
+        match Self::connect().await {
+            Err(e) if e.downcast_ref().is_some_and(is_access_denied) => Err(e),
+            Err(_) => load_watcher(Self::connect).await,
+            watcher => watcher,
+        }
+    }
+
+    // Doesn't wait for GNOME Shell to start
+    async fn probe(_: &Arc<ReportClient>) -> anyhow::Result<()> {
+        Self::connect().await.map(drop)
+    }
+
+    async fn run_iteration(&mut self, client: &Arc<ReportClient>) -> anyhow::Result<()> {
+        if let Some(window) = focused_window(&self.get_windows().await?) {
+            client
+                .send_active_window_with_extra(&window.app_id, &window.title, Some(window.extra))
+                .await
+                .with_context(|| "Failed to send heartbeat for active window")
+        } else {
+            trace!("No window is active");
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use zbus::zvariant::Value;
+
+    fn window(properties: &[(&str, Value<'static>)]) -> HashMap<String, OwnedValue> {
+        properties
+            .iter()
+            .map(|(name, value)| {
+                (
+                    name.to_string(),
+                    value.try_clone().unwrap().try_into().unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    #[rstest]
+    fn finds_focused_window() {
+        let windows = HashMap::from([
+            (
+                1,
+                window(&[
+                    ("title", Value::from("Files")),
+                    ("wm-class", Value::from("org.gnome.Nautilus")),
+                    ("has-focus", Value::from(false)),
+                ]),
+            ),
+            (
+                2,
+                window(&[
+                    ("title", Value::from("Inbox")),
+                    ("app-id", Value::from("org.mozilla.Thunderbird.desktop")),
+                    ("wm-class", Value::from("")),
+                    ("sandboxed-app-id", Value::from("org.mozilla.Thunderbird")),
+                    ("has-focus", Value::from(true)),
+                ]),
+            ),
+        ]);
+
+        let window = focused_window(&windows).unwrap();
+        assert_eq!("org.mozilla.Thunderbird", window.app_id);
+        assert_eq!("Inbox", window.title);
+        assert_eq!("2", window.extra["window_id"]);
+        assert_eq!("org.mozilla.Thunderbird", window.extra["desktop_file_name"]);
+        assert_eq!("org.mozilla.Thunderbird", window.extra["sandboxed_app_id"]);
+
+        assert!(focused_window(&HashMap::new()).is_none());
+    }
+}
@@ -20,6 +20,17 @@ struct WindowData {
 }
 
 impl WindowWatcher {
+    async fn connect() -> anyhow::Result<Self> {
+        let watcher = Self {
+            dbus_connection: Connection::session().await?,
+            last_app_id: String::new(),
+            last_title: String::new(),
+        };
+        watcher.get_window_data().await?;
+
+        Ok(watcher)
+    }
+
     async fn get_window_data(&self) -> anyhow::Result<WindowData> {
         let call_response = self
             .dbus_connection
@@ -87,17 +98,12 @@ impl WindowWatcher {
 #[async_trait]
 impl Watcher for WindowWatcher {
     async fn new(_: &Arc<ReportClient>) -> anyhow::Result<Self> {
-        load_watcher(|| async move {
-            let watcher = Self {
-                dbus_connection: Connection::session().await?,
-                last_app_id: String::new(),
-                last_title: String::new(),
-            };
-            watcher.get_window_data().await?;
+        load_watcher(Self::connect).await
+    }
 
-            Ok(watcher)
-        })
-        .await
+    // Doesn't wait for GNOME Shell to start
+    async fn probe(_: &Arc<ReportClient>) -> anyhow::Result<()> {
+        Self::connect().await.map(drop)
     }
 
     async fn run_iteration(&mut self, client: &Arc<ReportClient>) -> anyhow::Result<()> {
@@ -0,0 +1,272 @@
+/*
+ * Hyprland reports the changes as "EVENT>>DATA" lines on .socket2.sock,
+ * the active window is requested as JSON on .socket.sock after every change.
+ * https://wiki.hyprland.org/IPC/
+ */
+use super::Watcher;
+use crate::report_client::ReportClient;
+use anyhow::{anyhow, Context};
+use async_trait::async_trait;
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::env;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::net::UnixStream;
+
+// Events which may change the active window or its workspace, monitor and fullscreen state
+const WINDOW_EVENTS: &[&str] = &[
+    "activewindow",
+    "activewindowv2",
+    "windowtitle",
+    "windowtitlev2",
+    "workspace",
+    "workspacev2",
+    "focusedmon",
+    "focusedmonv2",
+    "fullscreen",
+    "movewindow",
+    "movewindowv2",
+    "closewindow",
+];
+
+#[derive(Deserialize, Default)]
+struct Workspace {
+    name: String,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct ActiveWindow {
+    address: String,
+    class: String,
+    title: String,
+    pid: i64,
+    workspace: Workspace,
+    monitor: i64,
+    // A boolean in older versions, a fullscreen mode number since 0.42
+    fullscreen: Value,
+}
+
+#[derive(Deserialize)]
+struct Monitor {
+    id: i64,
+    name: String,
+}
+
+struct WindowData {
+    app_id: String,
+    title: String,
+    extra: HashMap<String, String>,
+}
+
+struct HyprlandIpc {
+    socket_dir: PathBuf,
+}
+
+impl HyprlandIpc {
+    fn from_env() -> anyhow::Result<Self> {
+        let signature = env::var("HYPRLAND_INSTANCE_SIGNATURE")
+            .map_err(|_| anyhow!("HYPRLAND_INSTANCE_SIGNATURE is not set"))?;
+
+        // Hyprland 0.40 moved the sockets from /tmp
+        let socket_dir = dirs::runtime_dir()
+            .map(|dir| dir.join("hypr").join(&signature))
+            .filter(|dir| dir.exists())
+            .unwrap_or_else(|| PathBuf::from("/tmp/hypr").join(&signature));
+
+        Ok(Self { socket_dir })
+    }
+
+    async fn request<T: DeserializeOwned>(&self, command: &str) -> anyhow::Result<T> {
+        let path = self.socket_dir.join(".socket.sock");
+        let mut stream = UnixStream::connect(&path)
+            .await
+            .with_context(|| format!("Failed to connect to {}", path.display()))?;
+        stream.write_all(command.as_bytes()).await?;
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await?;
+        serde_json::from_str(&response)
+            .with_context(|| format!("Unexpected response to \"{command}\": {response}"))
+    }
+
+    async fn events(&self) -> anyhow::Result<Lines<BufReader<UnixStream>>> {
+        let path = self.socket_dir.join(".socket2.sock");
+        let stream = UnixStream::connect(&path)
+            .await
+            .with_context(|| format!("Failed to connect to {}", path.display()))?;
+
+        Ok(BufReader::new(stream).lines())
+    }
+
+    async fn active_window(&self) -> anyhow::Result<Option<WindowData>> {
+        let window: ActiveWindow = self.request("j/activewindow").await?;
+        // An empty object is returned if no window is focused
+        if window.address.is_empty() {
+            return Ok(None);
+        }
+        let monitors: Vec<Monitor> = self.request("j/monitors").await?;
+        let monitor = monitors
+            .into_iter()
+            .find(|monitor| monitor.id == window.monitor)
+            .map_or_else(|| window.monitor.to_string(), |monitor| monitor.name);
+        let fullscreen = match &window.fullscreen {
+            Value::Bool(fullscreen) => *fullscreen,
+            Value::Number(mode) => mode.as_i64() != Some(0),
+            _ => false,
+        };
+
+        let extra = HashMap::from([
+            ("address".to_string(), window.address),
+            ("workspace".to_string(), window.workspace.name),
+            ("monitor".to_string(), monitor),
+            ("fullscreen".to_string(), fullscreen.to_string()),
+            ("pid".to_string(), window.pid.to_string()),
+        ]);
+
+        Ok(Some(WindowData {
+            app_id: window.class,
+            title: window.title,
+            extra,
+        }))
+    }
+}
+
+pub struct WindowWatcher {
+    ipc: HyprlandIpc,
+    events: Lines<BufReader<UnixStream>>,
+}
+
+impl WindowWatcher {
+    async fn connect(ipc: HyprlandIpc) -> anyhow::Result<Self> {
+        let events = ipc.events().await?;
+        ipc.active_window().await?;
+
+        Ok(Self { ipc, events })
+    }
+}
+
+#[async_trait]
+impl Watcher for WindowWatcher {
+    async fn new(_: &Arc<ReportClient>) -> anyhow::Result<Self> {
+        Self::connect(HyprlandIpc::from_env()?).await
+    }
+
+    async fn run_iteration(&mut self, client: &Arc<ReportClient>) -> anyhow::Result<()> {
+        if let Some(window) = self.ipc.active_window().await? {
+            client
+                .send_active_window_with_extra(&window.app_id, &window.title, Some(window.extra))
+                .await
+                .with_context(|| "Failed to send heartbeat for active window")
+        } else {
+            info!("No window is active, skipping sending heartbeat");
+            Ok(())
+        }
+    }
+
+    async fn wait_for_event(&mut self) -> anyhow::Result<()> {
+        // Lines::next_line is cancel safe, a partially read line is kept for the next call
+        while let Some(line) = self.events.next_line().await? {
+            let event = line
+                .split_once(">>")
+                .map_or(line.as_str(), |(event, _)| event);
+            if WINDOW_EVENTS.contains(&event) {
+                trace!("Hyprland event: {line}");
+                return Ok(());
+            }
+        }
+        Err(anyhow!("Hyprland event socket is closed"))
+    }
+
+    fn is_event_driven(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use tempfile::TempDir;
+    use tokio::net::UnixListener;
+
+    const ACTIVE_WINDOW: &str = r#"{
+        "address": "0x55d5", "class": "kitty", "title": "vim", "pid": 4242,
+        "workspace": {"id": 3, "name": "code"}, "monitor": 1, "fullscreen": 1
+    }"#;
+    const MONITORS: &str = r#"[{"id": 0, "name": "eDP-1"}, {"id": 1, "name": "DP-2"}]"#;
+
+    fn fake_hyprland(active_window: &'static str, events: &'static str) -> TempDir {
+        let dir = tempfile::tempdir().unwrap();
+
+        let requests = UnixListener::bind(dir.path().join(".socket.sock")).unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = requests.accept().await {
+                let mut buffer = [0; 64];
+                let size = stream.read(&mut buffer).await.unwrap();
+                let response = match &buffer[..size] {
+                    b"j/activewindow" => active_window,
+                    b"j/monitors" => MONITORS,
+                    _ => "unknown request",
+                };
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+
+        let event_listener = UnixListener::bind(dir.path().join(".socket2.sock")).unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = event_listener.accept().await.unwrap();
+            stream.write_all(events.as_bytes()).await.unwrap();
+        });
+
+        dir
+    }
+
+    async fn watcher(dir: &TempDir) -> WindowWatcher {
+        let ipc = HyprlandIpc {
+            socket_dir: dir.path().to_path_buf(),
+        };
+        WindowWatcher::connect(ipc).await.unwrap()
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn reports_active_window() {
+        let dir = fake_hyprland(ACTIVE_WINDOW, "");
+        let watcher = watcher(&dir).await;
+
+        let window = watcher.ipc.active_window().await.unwrap().unwrap();
+        assert_eq!("kitty", window.app_id);
+        assert_eq!("vim", window.title);
+        assert_eq!("0x55d5", window.extra["address"]);
+        assert_eq!("code", window.extra["workspace"]);
+        assert_eq!("DP-2", window.extra["monitor"]);
+        assert_eq!("true", window.extra["fullscreen"]);
+        assert_eq!("4242", window.extra["pid"]);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn skips_missing_window() {
+        let dir = fake_hyprland("{}", "");
+        let watcher = watcher(&dir).await;
+
+        assert!(watcher.ipc.active_window().await.unwrap().is_none());
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn wakes_on_window_events() {
+        let events = "openlayer>>waybar\nactivewindowv2>>55d5\nfullscreen>>1\nopenlayer>>rofi\n";
+        let dir = fake_hyprland(ACTIVE_WINDOW, events);
+        let mut watcher = watcher(&dir).await;
+
+        watcher.wait_for_event().await.unwrap();
+        watcher.wait_for_event().await.unwrap();
+        // The rest are irrelevant, and the socket is closed after them
+        assert!(watcher.wait_for_event().await.is_err());
+    }
+}
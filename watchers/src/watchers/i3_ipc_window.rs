@@ -0,0 +1,311 @@
+/*
+ * Sway and i3 share the i3 IPC protocol, messages are "i3-ipc" followed by
+ * the payload length, the message type as native-endian u32 and the JSON payload.
+ * Window and workspace events wake the watcher, the focused window is found in the tree.
+ * https://i3wm.org/docs/ipc.html
+ */
+use super::Watcher;
+use crate::report_client::ReportClient;
+use anyhow::{anyhow, Context};
+use async_trait::async_trait;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::UnixStream;
+use tokio::sync::mpsc::{self, UnboundedReceiver};
+use tokio::task::AbortHandle;
+
+const MAGIC: &[u8] = b"i3-ipc";
+const SUBSCRIBE: u32 = 2;
+const GET_TREE: u32 = 4;
+
+#[derive(Deserialize)]
+struct Success {
+    success: bool,
+}
+
+#[derive(Deserialize)]
+struct WindowProperties {
+    class: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct Node {
+    id: i64,
+    #[serde(rename = "type")]
+    node_type: String,
+    name: Option<String>,
+    // Sway only, X11 windows have window_properties instead
+    app_id: Option<String>,
+    pid: Option<i64>,
+    window_properties: Option<WindowProperties>,
+    focused: bool,
+    nodes: Vec<Node>,
+    floating_nodes: Vec<Node>,
+}
+
+struct WindowData {
+    app_id: String,
+    title: String,
+    extra: HashMap<String, String>,
+}
+
+struct I3Connection {
+    stream: UnixStream,
+}
+
+impl I3Connection {
+    async fn connect(path: &Path) -> anyhow::Result<Self> {
+        let stream = UnixStream::connect(path)
+            .await
+            .with_context(|| format!("Failed to connect to {}", path.display()))?;
+
+        Ok(Self { stream })
+    }
+
+    async fn send(&mut self, message_type: u32, payload: &str) -> anyhow::Result<()> {
+        let mut message = MAGIC.to_vec();
+        message.extend(u32::try_from(payload.len())?.to_ne_bytes());
+        message.extend(message_type.to_ne_bytes());
+        message.extend(payload.as_bytes());
+
+        Ok(self.stream.write_all(&message).await?)
+    }
+
+    async fn receive(&mut self) -> anyhow::Result<(u32, Vec<u8>)> {
+        let mut header = [0; 14];
+        self.stream.read_exact(&mut header).await?;
+        if &header[..6] != MAGIC {
+            return Err(anyhow!("Unexpected i3 IPC message header"));
+        }
+        let length = u32::from_ne_bytes(header[6..10].try_into()?);
+        let message_type = u32::from_ne_bytes(header[10..14].try_into()?);
+
+        let mut payload = vec![0; usize::try_from(length)?];
+        self.stream.read_exact(&mut payload).await?;
+
+        Ok((message_type, payload))
+    }
+
+    async fn request<T: for<'de> Deserialize<'de>>(
+        &mut self,
+        message_type: u32,
+        payload: &str,
+    ) -> anyhow::Result<T> {
+        self.send(message_type, payload).await?;
+        // Events are not subscribed on this connection, so the next message is the reply
+        let (_, reply) = self.receive().await?;
+
+        serde_json::from_slice(&reply).with_context(|| "Unexpected i3 IPC reply")
+    }
+
+    async fn active_window(&mut self) -> anyhow::Result<Option<WindowData>> {
+        let tree: Node = self.request(GET_TREE, "").await?;
+
+        Ok(find_focused(&tree, "", ""))
+    }
+}
+
+fn find_focused(node: &Node, workspace: &str, output: &str) -> Option<WindowData> {
+    let (workspace, output) = match node.node_type.as_str() {
+        "workspace" => (node.name.as_deref().unwrap_or_default(), output),
+        "output" => (workspace, node.name.as_deref().unwrap_or_default()),
+        _ => (workspace, output),
+    };
+
+    // A focused workspace or output has no window
+    if node.focused && (node.node_type == "con" || node.node_type == "floating_con") {
+        let app_id = node.app_id.clone().or_else(|| {
+            node.window_properties
+                .as_ref()
+                .and_then(|properties| properties.class.clone())
+        });
+        let mut extra = HashMap::from([
+            ("workspace".to_string(), workspace.to_string()),
+            ("output".to_string(), output.to_string()),
+            ("con_id".to_string(), node.id.to_string()),
+        ]);
+        if let Some(pid) = node.pid {
+            extra.insert("pid".to_string(), pid.to_string());
+        }
+
+        return Some(WindowData {
+            app_id: app_id.unwrap_or_default(),
+            title: node.name.clone().unwrap_or_default(),
+            extra,
+        });
+    }
+
+    node.nodes
+        .iter()
+        .chain(&node.floating_nodes)
+        .find_map(|child| find_focused(child, workspace, output))
+}
+
+pub struct WindowWatcher {
+    connection: I3Connection,
+    events: UnboundedReceiver<()>,
+    events_reader: AbortHandle,
+}
+
+impl WindowWatcher {
+    async fn connect(path: &Path) -> anyhow::Result<Self> {
+        let mut connection = I3Connection::connect(path).await?;
+        connection.active_window().await?;
+
+        let mut event_connection = I3Connection::connect(path).await?;
+        let reply: Success = event_connection
+            .request(SUBSCRIBE, r#"["window","workspace"]"#)
+            .await?;
+        if !reply.success {
+            return Err(anyhow!("Failed to subscribe to i3 IPC events"));
+        }
+
+        // Messages are read in a task as reading a message is not cancel safe
+        let (sender, events) = mpsc::unbounded_channel();
+        let events_reader = tokio::spawn(async move {
+            loop {
+                match event_connection.receive().await {
+                    Ok((message_type, _)) => {
+                        trace!("i3 IPC event: {message_type:#x}");
+                        if sender.send(()).is_err() {
+                            break;
+                        }
+                    }
+                    Err(e) => {
+                        error!("i3 IPC event connection is broken: {e}");
+                        break;
+                    }
+                }
+            }
+        })
+        .abort_handle();
+
+        Ok(Self {
+            connection,
+            events,
+            events_reader,
+        })
+    }
+}
+
+impl Drop for WindowWatcher {
+    fn drop(&mut self) {
+        self.events_reader.abort();
+    }
+}
+
+#[async_trait]
+impl Watcher for WindowWatcher {
+    async fn new(_: &Arc<ReportClient>) -> anyhow::Result<Self> {
+        let path = env::var("SWAYSOCK")
+            .or_else(|_| env::var("I3SOCK"))
+            .map_err(|_| anyhow!("Neither SWAYSOCK nor I3SOCK is set"))?;
+
+        Self::connect(&PathBuf::from(path)).await
+    }
+
+    async fn run_iteration(&mut self, client: &Arc<ReportClient>) -> anyhow::Result<()> {
+        if let Some(window) = self.connection.active_window().await? {
+            client
+                .send_active_window_with_extra(&window.app_id, &window.title, Some(window.extra))
+                .await
+                .with_context(|| "Failed to send heartbeat for active window")
+        } else {
+            info!("No window is focused, skipping sending heartbeat");
+            Ok(())
+        }
+    }
+
+    async fn wait_for_event(&mut self) -> anyhow::Result<()> {
+        self.events
+            .recv()
+            .await
+            .ok_or_else(|| anyhow!("i3 IPC event connection is closed"))
+    }
+
+    fn is_event_driven(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use tempfile::TempDir;
+    use tokio::net::UnixListener;
+
+    const TREE: &str = r#"{"id": 1, "type": "root", "name": "root", "nodes": [
+        {"id": 2, "type": "output", "name": "__i3", "nodes": []},
+        {"id": 3, "type": "output", "name": "eDP-1", "nodes": [
+            {"id": 4, "type": "workspace", "name": "1: term", "nodes": [
+                {"id": 10, "type": "con", "name": "vim", "app_id": "foot", "pid": 100, "nodes": []}
+            ]},
+            {"id": 5, "type": "workspace", "name": "2: web", "nodes": [], "floating_nodes": [
+                {"id": 11, "type": "floating_con", "name": "Firefox", "app_id": null,
+                 "window_properties": {"class": "firefox"}, "focused": true, "nodes": []}
+            ]}
+        ]}
+    ]}"#;
+
+    fn fake_i3() -> (TempDir, PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ipc.sock");
+        let listener = UnixListener::bind(&path).unwrap();
+
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let mut connection = I3Connection { stream };
+                tokio::spawn(async move {
+                    while let Ok((message_type, _)) = connection.receive().await {
+                        if message_type == GET_TREE {
+                            connection.send(GET_TREE, TREE).await.unwrap();
+                        } else {
+                            connection
+                                .send(SUBSCRIBE, r#"{"success": true}"#)
+                                .await
+                                .unwrap();
+                            connection.send(0x8000_0003, "{}").await.unwrap();
+                            connection.send(0x8000_0000, "{}").await.unwrap();
+                            // Closes the connection after the events
+                            return;
+                        }
+                    }
+                });
+            }
+        });
+
+        (dir, path)
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn finds_focused_window() {
+        let (_dir, path) = fake_i3();
+        let mut watcher = WindowWatcher::connect(&path).await.unwrap();
+
+        let window = watcher.connection.active_window().await.unwrap().unwrap();
+        assert_eq!("firefox", window.app_id);
+        assert_eq!("Firefox", window.title);
+        assert_eq!("2: web", window.extra["workspace"]);
+        assert_eq!("eDP-1", window.extra["output"]);
+        assert_eq!("11", window.extra["con_id"]);
+        assert!(!window.extra.contains_key("pid"));
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn wakes_on_events() {
+        let (_dir, path) = fake_i3();
+        let mut watcher = WindowWatcher::connect(&path).await.unwrap();
+
+        watcher.wait_for_event().await.unwrap();
+        watcher.wait_for_event().await.unwrap();
+        assert!(watcher.wait_for_event().await.is_err());
+    }
+}
@@ -5,7 +5,12 @@ pub struct Tracker {
     last_input_time: DateTime<Utc>,
     is_idle: bool,
     is_changed: bool,
+    // Timeout of the idle notifications
     idle_timeout: TimeDelta,
+    // Timeout of the focused window, the notifications wait for it if it's longer
+    window_timeout: TimeDelta,
+    // The notification came, but the window timeout hasn't passed since the input
+    pending_idle_since: Option<DateTime<Utc>>,
 
     idle_end: Option<DateTime<Utc>>,
 }
@@ -29,10 +34,16 @@ impl Tracker {
             is_idle: false,
             is_changed: false,
             idle_timeout,
+            window_timeout: idle_timeout,
+            pending_idle_since: None,
             idle_end: None,
         }
     }
 
+    pub fn set_window_timeout(&mut self, window_timeout: TimeDelta) {
+        self.window_timeout = window_timeout;
+    }
+
     fn set_idle(&mut self, is_idle: bool) {
         self.is_idle = is_idle;
         self.is_changed = true;
@@ -41,12 +52,18 @@ impl Tracker {
     pub fn mark_not_idle(&mut self, now: DateTime<Utc>) {
         debug!("No longer idle");
         self.last_input_time = now;
+        self.pending_idle_since = None;
         self.set_idle(false);
 
         self.idle_end = Some(now);
     }
 
-    pub fn mark_idle(&mut self, _: DateTime<Utc>) {
+    pub fn mark_idle(&mut self, now: DateTime<Utc>) {
+        if self.window_timeout > self.idle_timeout {
+            debug!("Idle, waiting for the timeout of the window");
+            self.pending_idle_since = Some(now - self.idle_timeout);
+            return;
+        }
         debug!("Idle again");
         self.set_idle(true);
     }
@@ -63,12 +80,14 @@ impl Tracker {
         self.last_input_time = now - time_since_input;
 
         if self.is_idle
-            && u64::from(seconds_since_input) < self.idle_timeout.num_seconds().try_into().unwrap()
+            && u64::from(seconds_since_input)
+                < self.window_timeout.num_seconds().try_into().unwrap()
         {
             debug!("No longer idle");
             self.set_idle(false);
         } else if !self.is_idle
-            && u64::from(seconds_since_input) >= self.idle_timeout.num_seconds().try_into().unwrap()
+            && u64::from(seconds_since_input)
+                >= self.window_timeout.num_seconds().try_into().unwrap()
         {
             debug!("Idle again");
             self.set_idle(true);
@@ -78,7 +97,15 @@ impl Tracker {
     }
 
     pub fn get_reactive(&mut self, now: DateTime<Utc>) -> anyhow::Result<Status> {
-        if !self.is_idle {
+        if let Some(idle_since) = self.pending_idle_since {
+            // Active until the window timeout passes since the last input
+            self.last_input_time = max(self.last_input_time, idle_since);
+            if now - idle_since >= self.window_timeout {
+                debug!("Idle again");
+                self.pending_idle_since = None;
+                self.set_idle(true);
+            }
+        } else if !self.is_idle {
             self.last_input_time = max(self.last_input_time, now - self.idle_timeout);
 
             if let Some(idle_end) = self.idle_end {
@@ -401,4 +428,45 @@ mod tests {
             matches!(status, Status::Idle { last_input_time, .. } if last_input_time == time.now - Duration::seconds(10))
         );
     }
+
+    #[rstest]
+    fn test_window_timeout() {
+        let mut time = TimeReactive::new();
+        let mut tracker = Tracker::new(time.now, Duration::seconds(30));
+        tracker.set_window_timeout(Duration::seconds(60));
+
+        // Notified after 30 seconds without input
+        time.tick(40);
+        tracker.mark_idle(time.now);
+        assert!(!tracker.is_idle);
+
+        time.tick(5);
+        let status = tracker.get_reactive(time.now).unwrap();
+        time.assert_active_status(
+            &status,
+            false,
+            35,
+            "Input stopped 30s before the notification",
+        );
+
+        time.tick(20);
+        let status = tracker.get_reactive(time.now).unwrap();
+        time.assert_active_status(&status, false, 55, "The window timeout hasn't passed");
+
+        time.tick(10);
+        let status = tracker.get_reactive(time.now).unwrap();
+        time.assert_idle_status(&status, true, 65, "Idle since the last input");
+
+        time.tick(5);
+        tracker.mark_not_idle(time.now);
+        let status = tracker.get_reactive(time.now).unwrap();
+        time.assert_active_status(&status, true, 0, "");
+
+        let mut tracker = Tracker::new(time.now, Duration::seconds(30));
+        tracker.set_window_timeout(Duration::seconds(60));
+        let status = tracker.get_with_last_input(time.now, 40).unwrap();
+        assert!(matches!(status, Status::Active { changed: false, .. }));
+        let status = tracker.get_with_last_input(time.now, 60).unwrap();
+        assert!(matches!(status, Status::Idle { changed: true, .. }));
+    }
 }
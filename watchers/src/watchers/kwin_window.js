@@ -1,5 +1,31 @@
 let connections = {};
 
+// KDE 6 has virtual desktop objects and outputs, KDE 5 has desktop numbers and screens
+function desktops(client) {
+    if ("desktops" in client) {
+        return client.desktops.map(function(desktop) { return desktop.name; }).join(",");
+    }
+    return "desktop" in client ? String(client.desktop) : "";
+}
+
+function output(client) {
+    if ("output" in client && client.output) {
+        return client.output.name;
+    }
+    return "screen" in client ? String(client.screen) : "";
+}
+
+function details(client) {
+    return JSON.stringify({
+        pid: "pid" in client ? String(client.pid) : "",
+        desktops: desktops(client),
+        activities: "activities" in client ? client.activities.join(",") : "",
+        fullscreen: "fullScreen" in client ? String(client.fullScreen) : "",
+        output: output(client),
+        desktop_file_name: "desktopFileName" in client ? String(client.desktopFileName) : ""
+    });
+}
+
 function send(client) {
     callDBus(
         "com._2e3s.Awatcher",
@@ -8,19 +34,34 @@ function send(client) {
         "NotifyActiveWindow",
         "caption" in client ? client.caption : "",
         "resourceClass" in client ? String(client.resourceClass) : "",
-        "resourceName" in client ? String(client.resourceName) : ""
+        "resourceName" in client ? String(client.resourceName) : "",
+        details(client)
     );
 }
 
+let changeSignals = [
+    "captionChanged",
+    "fullScreenChanged",
+    "desktopsChanged",
+    "desktopChanged",
+    "activitiesChanged",
+    "outputChanged",
+    "screenChanged"
+];
+
 let handler = function(client){
     if (client === null) {
         return;
     }
     if (!(client.internalId in connections)) {
         connections[client.internalId] = true;
-        client.captionChanged.connect(function() {
-            if (client.active) {
-                send(client);
+        changeSignals.forEach(function(signal) {
+            if (signal in client) {
+                client[signal].connect(function() {
+                    if (client.active) {
+                        send(client);
+                    }
+                });
             }
         });
     }
@@ -7,15 +7,18 @@ use super::Watcher;
 use crate::report_client::ReportClient;
 use anyhow::{anyhow, Context};
 use async_trait::async_trait;
+use serde::Deserialize;
+use std::collections::HashMap;
 use std::env::{self, temp_dir};
 use std::path::Path;
 use std::sync::{mpsc::channel, Arc};
-use std::thread;
-use tokio::sync::Mutex;
+use std::thread::{self, JoinHandle};
+use tokio::sync::{oneshot, Mutex, Notify};
 use zbus::interface;
 use zbus::{conn::Builder as ConnectionBuilder, Connection};
 
 const KWIN_SCRIPT_NAME: &str = "activity_watcher";
+const DBUS_NAME: &str = "com._2e3s.Awatcher";
 const KWIN_SCRIPT: &str = include_str!("kwin_window.js");
 
 struct KWinScript {
@@ -39,9 +42,10 @@ impl KWinScript {
         let number = self.get_registered_number(&path).await?;
         let result = self.start(number).await;
         std::fs::remove_file(&path)?;
+        result?;
         self.is_loaded = true;
 
-        result
+        Ok(())
     }
 
     async fn is_loaded(&self) -> anyhow::Result<bool> {
@@ -172,6 +176,33 @@ impl KWinScript {
     }
 }
 
+// Another instance would lose its name and the script
+async fn check_name_is_free(connection: &Connection) -> anyhow::Result<()> {
+    let has_owner: bool = connection
+        .call_method(
+            Some("org.freedesktop.DBus"),
+            "/org/freedesktop/DBus",
+            Some("org.freedesktop.DBus"),
+            "NameHasOwner",
+            &DBUS_NAME,
+        )
+        .await?
+        .body()
+        .deserialize()?;
+    if has_owner {
+        Err(anyhow!(
+            "{DBUS_NAME} is already owned, another awatcher may be running"
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+fn is_x11() -> bool {
+    env::var("WAYLAND_DISPLAY").is_err()
+        && env::var_os("XDG_SESSION_TYPE").unwrap_or("".into()) == "x11"
+}
+
 impl Drop for KWinScript {
     fn drop(&mut self) {
         if self.is_loaded {
@@ -197,7 +228,11 @@ async fn send_active_window(
 
     if let Some(active_window) = active_window.as_ref() {
         client
-            .send_active_window(&active_window.resource_class, &active_window.caption)
+            .send_active_window_with_extra(
+                &active_window.resource_class,
+                &active_window.caption,
+                Some(active_window.details.extra_data()),
+            )
             .await
             .with_context(|| "Failed to send heartbeat for active window")
     } else {
@@ -211,10 +246,41 @@ struct ActiveWindow {
     resource_class: String,
     resource_name: String,
     caption: String,
+    details: WindowDetails,
+}
+
+// Sent by the script as JSON, the values are empty if KWin doesn't have them
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct WindowDetails {
+    pid: String,
+    desktops: String,
+    activities: String,
+    fullscreen: String,
+    output: String,
+    desktop_file_name: String,
+}
+
+impl WindowDetails {
+    fn extra_data(&self) -> HashMap<String, String> {
+        [
+            ("pid", &self.pid),
+            ("desktops", &self.desktops),
+            ("activities", &self.activities),
+            ("fullscreen", &self.fullscreen),
+            ("output", &self.output),
+            ("desktop_file_name", &self.desktop_file_name),
+        ]
+        .into_iter()
+        .filter(|(_, value)| !value.is_empty())
+        .map(|(key, value)| (key.to_string(), value.clone()))
+        .collect()
+    }
 }
 
 struct ActiveWindowInterface {
     active_window: Arc<Mutex<Option<ActiveWindow>>>,
+    changed: Arc<Notify>,
 }
 
 #[interface(name = "com._2e3s.Awatcher")]
@@ -224,62 +290,52 @@ impl ActiveWindowInterface {
         caption: String,
         resource_class: String,
         resource_name: String,
+        details: String,
     ) {
-        debug!("Active window class: \"{resource_class}\", name: \"{resource_name}\", caption: \"{caption}\"");
+        debug!("Active window class: \"{resource_class}\", name: \"{resource_name}\", caption: \"{caption}\", details: {details}");
+        let details = serde_json::from_str(&details).unwrap_or_else(|e| {
+            error!("Invalid window details from KWin script: {e}");
+            WindowDetails::default()
+        });
         let mut active_window = self.active_window.lock().await;
         if let Some(active_window) = active_window.as_mut() {
             active_window.caption = caption;
             active_window.resource_class = resource_class;
             active_window.resource_name = resource_name;
+            active_window.details = details;
         } else {
             *active_window = Some(ActiveWindow {
                 caption,
                 resource_class,
                 resource_name,
+                details,
             });
         }
+        self.changed.notify_one();
     }
 }
 
-pub struct WindowWatcher {
-    active_window: Arc<Mutex<Option<ActiveWindow>>>,
-    // Prolong its lifetime
-    _kwin_script: KWinScript,
+// Serves the interface for the script in its own thread until dropped, releasing the name.
+struct DBusInterface {
+    shutdown: Option<oneshot::Sender<()>>,
+    thread: Option<JoinHandle<()>>,
 }
 
-#[async_trait]
-impl Watcher for WindowWatcher {
-    async fn new(_: &Arc<ReportClient>) -> anyhow::Result<Self> {
-        let mut kwin_script = KWinScript::new(Connection::session().await?);
-        if kwin_script.is_loaded().await? {
-            debug!("KWin script is already loaded, unloading");
-            kwin_script.unload().await?;
-        }
-        if env::var("WAYLAND_DISPLAY").is_err()
-            && env::var_os("XDG_SESSION_TYPE").unwrap_or("".into()) == "x11"
-        {
-            return Err(anyhow!("X11 should be tried instead"));
+impl DBusInterface {
+    fn start(active_window_interface: ActiveWindowInterface) -> anyhow::Result<Self> {
+        async fn get_connection(
+            active_window_interface: ActiveWindowInterface,
+        ) -> zbus::Result<Connection> {
+            ConnectionBuilder::session()?
+                .name(DBUS_NAME)?
+                .serve_at("/com/_2e3s/Awatcher", active_window_interface)?
+                .build()
+                .await
         }
 
-        kwin_script.load().await.unwrap();
-
-        let active_window = Arc::new(Mutex::new(None));
-        let active_window_interface: ActiveWindowInterface = ActiveWindowInterface {
-            active_window: Arc::clone(&active_window),
-        };
-
         let (tx, rx) = channel();
-        thread::spawn(move || {
-            async fn get_connection(
-                active_window_interface: ActiveWindowInterface,
-            ) -> zbus::Result<Connection> {
-                ConnectionBuilder::session()?
-                    .name("com._2e3s.Awatcher")?
-                    .serve_at("/com/_2e3s/Awatcher", active_window_interface)?
-                    .build()
-                    .await
-            }
-
+        let (shutdown, shutdown_rx) = oneshot::channel();
+        let thread = thread::spawn(move || {
             tokio::runtime::Builder::new_current_thread()
                 .enable_all()
                 .build()
@@ -288,26 +344,132 @@ impl Watcher for WindowWatcher {
                     match get_connection(active_window_interface).await {
                         Ok(connection) => {
                             tx.send(None).unwrap();
-                            loop {
-                                connection.monitor_activity().await;
+                            let monitor = async {
+                                loop {
+                                    connection.monitor_activity().await;
+                                }
+                            };
+                            tokio::select! {
+                                () = monitor => {},
+                                _ = shutdown_rx => debug!("Stopping the DBus interface"),
                             }
                         }
-                        Err(e) => tx.send(Some(e)),
+                        Err(e) => tx.send(Some(e)).unwrap(),
                     }
-                })
-                .unwrap();
+                });
         });
-        if let Some(error) = rx.recv().unwrap() {
-            panic!("Failed to run a DBus interface: {error}");
+
+        match rx.recv()? {
+            Some(error) => Err(anyhow!("Failed to run a DBus interface: {error}")),
+            None => Ok(Self {
+                shutdown: Some(shutdown),
+                thread: Some(thread),
+            }),
         }
+    }
+}
+
+impl Drop for DBusInterface {
+    fn drop(&mut self) {
+        self.shutdown.take();
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("DBus interface thread panicked");
+            }
+        }
+    }
+}
+
+pub struct WindowWatcher {
+    active_window: Arc<Mutex<Option<ActiveWindow>>>,
+    changed: Arc<Notify>,
+    kwin_script: KWinScript,
+    // Prolong its lifetime
+    _dbus_interface: DBusInterface,
+}
+
+#[async_trait]
+impl Watcher for WindowWatcher {
+    async fn new(_: &Arc<ReportClient>) -> anyhow::Result<Self> {
+        let connection = Connection::session().await?;
+        check_name_is_free(&connection).await?;
+        let mut kwin_script = KWinScript::new(connection);
+        if kwin_script.is_loaded().await? {
+            debug!("KWin script is already loaded, unloading");
+            kwin_script.unload().await?;
+        }
+        if is_x11() {
+            return Err(anyhow!("X11 should be tried instead"));
+        }
+
+        let active_window = Arc::new(Mutex::new(None));
+        let changed = Arc::new(Notify::new());
+        // The script reports to this interface, so it must be served first
+        let dbus_interface = DBusInterface::start(ActiveWindowInterface {
+            active_window: Arc::clone(&active_window),
+            changed: Arc::clone(&changed),
+        })?;
+        kwin_script.load().await?;
 
         Ok(Self {
             active_window,
-            _kwin_script: kwin_script,
+            changed,
+            kwin_script,
+            _dbus_interface: dbus_interface,
         })
     }
 
+    // Neither loads the script nor takes the name
+    async fn probe(_: &Arc<ReportClient>) -> anyhow::Result<()> {
+        let connection = Connection::session().await?;
+        check_name_is_free(&connection).await?;
+        KWinScript::new(connection).is_loaded().await?;
+        if is_x11() {
+            return Err(anyhow!("X11 should be tried instead"));
+        }
+        Ok(())
+    }
+
     async fn run_iteration(&mut self, client: &Arc<ReportClient>) -> anyhow::Result<()> {
+        // A restarted KWin doesn't have the script anymore
+        if !self.kwin_script.is_loaded().await? {
+            warn!("KWin script is not loaded, loading it again");
+            self.active_window.lock().await.take();
+            self.kwin_script.load().await?;
+        }
+
         send_active_window(client, &self.active_window).await
     }
+
+    async fn wait_for_event(&mut self) -> anyhow::Result<()> {
+        self.changed.notified().await;
+        Ok(())
+    }
+
+    fn is_event_driven(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    fn reports_window_details() {
+        let details: WindowDetails = serde_json::from_str(
+            r#"{"pid": "4242", "desktops": "Desktop 1,Desktop 2", "activities": "",
+                "fullscreen": "false", "output": "DP-1", "desktop_file_name": "org.kde.konsole"}"#,
+        )
+        .unwrap();
+
+        let extra = details.extra_data();
+        assert_eq!(5, extra.len());
+        assert_eq!("4242", extra["pid"]);
+        assert_eq!("Desktop 1,Desktop 2", extra["desktops"]);
+        assert_eq!("false", extra["fullscreen"]);
+        assert_eq!("DP-1", extra["output"]);
+        assert_eq!("org.kde.konsole", extra["desktop_file_name"]);
+    }
 }
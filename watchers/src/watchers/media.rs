@@ -0,0 +1,344 @@
+/*
+ * MPRIS players are found by their bus names and report PlaybackStatus on /org/mpris/MediaPlayer2.
+ * The players are followed by NameOwnerChanged, and their status by PropertiesChanged,
+ * which are sent from the unique name of the player's connection.
+ * https://specifications.freedesktop.org/mpris-spec/latest/
+ * The idle inhibitors of X11 sessions are taken from GNOME Session Manager or PowerManagement (KDE)
+ * only if enabled, they don't tell the application to filter by the players' lists.
+ * Wayland inhibitors are respected by the compositors in the idle notifications already.
+ */
+use crate::config::MediaPlayers;
+use crate::report_client::ReportClient;
+use anyhow::anyhow;
+use futures_util::StreamExt;
+use std::collections::HashMap;
+use std::sync::Arc;
+use zbus::message::Type as MessageType;
+use zbus::zvariant::OwnedValue;
+use zbus::{Connection, MatchRule, Message, MessageStream, Proxy};
+
+const MPRIS_PREFIX: &str = "org.mpris.MediaPlayer2.";
+const MPRIS_PATH: &str = "/org/mpris/MediaPlayer2";
+const PLAYER_INTERFACE: &str = "org.mpris.MediaPlayer2.Player";
+// GSM_INHIBITOR_FLAG_IDLE
+const GNOME_INHIBIT_IDLE: u32 = 8;
+
+// The bus name without the prefix and the instance suffix, e.g. "firefox" of
+// org.mpris.MediaPlayer2.firefox.instance_1_42
+fn bus_player_name(bus_name: &str) -> Option<&str> {
+    let name = bus_name.strip_prefix(MPRIS_PREFIX)?;
+    Some(name.split_once(".instance").map_or(name, |(name, _)| name))
+}
+
+async fn get_property(
+    connection: &Connection,
+    destination: &str,
+    interface: &str,
+    name: &str,
+) -> anyhow::Result<OwnedValue> {
+    let reply = connection
+        .call_method(
+            Some(destination),
+            MPRIS_PATH,
+            Some("org.freedesktop.DBus.Properties"),
+            "Get",
+            &(interface, name),
+        )
+        .await?;
+
+    Ok(reply.body().deserialize()?)
+}
+
+async fn player_name(connection: &Connection, bus_name: &str) -> Option<String> {
+    let desktop_entry = get_property(
+        connection,
+        bus_name,
+        "org.mpris.MediaPlayer2",
+        "DesktopEntry",
+    )
+    .await
+    .ok()
+    .and_then(|value| String::try_from(value).ok())
+    .filter(|entry| !entry.is_empty());
+
+    desktop_entry.or_else(|| bus_player_name(bus_name).map(ToString::to_string))
+}
+
+struct Player {
+    name: Option<String>,
+    is_playing: bool,
+}
+
+// Players by the unique bus name of their connection, which sends the signals
+type Players = HashMap<String, Player>;
+
+async fn load_player(connection: &Connection, bus_name: &str) -> Player {
+    let status = get_property(connection, bus_name, PLAYER_INTERFACE, "PlaybackStatus").await;
+    let is_playing = match status {
+        Ok(value) => <&str>::try_from(&value).is_ok_and(|status| status == "Playing"),
+        Err(e) => {
+            trace!("No playback status of {bus_name}: {e}");
+            false
+        }
+    };
+
+    Player {
+        name: player_name(connection, bus_name).await,
+        is_playing,
+    }
+}
+
+async fn load_players(connection: &Connection) -> anyhow::Result<Players> {
+    let dbus = Proxy::new(
+        connection,
+        "org.freedesktop.DBus",
+        "/org/freedesktop/DBus",
+        "org.freedesktop.DBus",
+    )
+    .await?;
+    let names: Vec<String> = dbus.call("ListNames", &()).await?;
+
+    let mut players = HashMap::new();
+    for bus_name in names.iter().filter(|name| name.starts_with(MPRIS_PREFIX)) {
+        match dbus.call::<_, _, String>("GetNameOwner", bus_name).await {
+            Ok(owner) => {
+                players.insert(owner, load_player(connection, bus_name).await);
+            }
+            Err(e) => trace!("{bus_name} is gone: {e}"),
+        }
+    }
+
+    Ok(players)
+}
+
+fn is_playing(players: &Players, allowed: &MediaPlayers) -> bool {
+    players
+        .values()
+        .filter(|player| player.is_playing)
+        .filter_map(|player| player.name.as_deref())
+        .any(|name| {
+            let is_allowed = allowed.is_allowed(name);
+            trace!(
+                "{name} is playing{}",
+                if is_allowed { "" } else { ", ignored" }
+            );
+            is_allowed
+        })
+}
+
+enum PlayerChange {
+    // The sender and whether it plays
+    Playback(String, bool),
+    // The bus name, the previous and the new owner
+    Owner(String, String, String),
+}
+
+fn player_change(message: &Message) -> Option<PlayerChange> {
+    let header = message.header();
+    let interface = header.interface()?.as_str();
+    let member = header.member()?.as_str();
+
+    match (interface, member) {
+        ("org.freedesktop.DBus.Properties", "PropertiesChanged") => {
+            let (interface, changed, _): (String, HashMap<String, OwnedValue>, Vec<String>) =
+                message.body().deserialize().ok()?;
+            if interface != PLAYER_INTERFACE {
+                return None;
+            }
+            let status = <&str>::try_from(changed.get("PlaybackStatus")?).ok()?;
+            let sender = header.sender()?.to_string();
+            Some(PlayerChange::Playback(sender, status == "Playing"))
+        }
+        ("org.freedesktop.DBus", "NameOwnerChanged") => {
+            let (name, old_owner, new_owner): (String, String, String) =
+                message.body().deserialize().ok()?;
+            name.starts_with(MPRIS_PREFIX)
+                .then_some(PlayerChange::Owner(name, old_owner, new_owner))
+        }
+        _ => None,
+    }
+}
+
+async fn apply_change(connection: &Connection, players: &mut Players, change: PlayerChange) {
+    match change {
+        PlayerChange::Playback(sender, is_playing) => {
+            if let Some(player) = players.get_mut(&sender) {
+                player.is_playing = is_playing;
+            }
+        }
+        PlayerChange::Owner(name, old_owner, new_owner) => {
+            players.remove(&old_owner);
+            if !new_owner.is_empty() {
+                debug!("Media player {name} appeared");
+                players.insert(new_owner, load_player(connection, &name).await);
+            }
+        }
+    }
+}
+
+async fn player_messages(
+    connection: &Connection,
+) -> anyhow::Result<(MessageStream, MessageStream)> {
+    let properties = MatchRule::builder()
+        .msg_type(MessageType::Signal)
+        .interface("org.freedesktop.DBus.Properties")?
+        .member("PropertiesChanged")?
+        .path(MPRIS_PATH)?
+        .arg(0, PLAYER_INTERFACE)?
+        .build();
+    let owners = MatchRule::builder()
+        .msg_type(MessageType::Signal)
+        .sender("org.freedesktop.DBus")?
+        .interface("org.freedesktop.DBus")?
+        .member("NameOwnerChanged")?
+        .arg0ns("org.mpris.MediaPlayer2")?
+        .build();
+
+    Ok((
+        MessageStream::for_match_rule(properties, connection, None).await?,
+        MessageStream::for_match_rule(owners, connection, None).await?,
+    ))
+}
+
+async fn is_idle_inhibited(connection: &Connection) -> bool {
+    let gnome = connection
+        .call_method(
+            Some("org.gnome.SessionManager"),
+            "/org/gnome/SessionManager",
+            Some("org.gnome.SessionManager"),
+            "IsInhibited",
+            &GNOME_INHIBIT_IDLE,
+        )
+        .await;
+    let reply = match gnome {
+        Ok(reply) => Ok(reply),
+        Err(_) => {
+            connection
+                .call_method(
+                    Some("org.freedesktop.PowerManagement"),
+                    "/org/freedesktop/PowerManagement/Inhibit",
+                    Some("org.freedesktop.PowerManagement.Inhibit"),
+                    "HasInhibit",
+                    &(),
+                )
+                .await
+        }
+    };
+
+    match reply {
+        Ok(reply) => reply.body().deserialize().unwrap_or(false),
+        Err(e) => {
+            trace!("Idle inhibitors are unknown: {e}");
+            false
+        }
+    }
+}
+
+/// Reports to the client whether media plays or idle is inhibited.
+///
+/// The players are watched by the signals, the inhibitors are polled with the idle period.
+pub async fn run_media_watcher(client: Arc<ReportClient>) -> anyhow::Result<()> {
+    let connection = Connection::session().await?;
+    let poll_time = client.config.poll_time_idle.to_std()?;
+    let media = &client.config.media_players;
+    // Subscribed before loading the players to miss no changes
+    let (mut properties, mut owners) = player_messages(&connection).await?;
+    let mut players = load_players(&connection).await?;
+
+    loop {
+        let is_playing = is_playing(&players, media)
+            || (media.inhibitors && is_idle_inhibited(&connection).await);
+        client.set_media_playing(is_playing, client.clock.now());
+
+        let message = tokio::select! {
+            message = properties.next() => message,
+            message = owners.next() => message,
+            () = client.clock.sleep(poll_time), if media.inhibitors => continue,
+        };
+        match message {
+            Some(Ok(message)) => {
+                if let Some(change) = player_change(&message) {
+                    apply_change(&connection, &mut players, change).await;
+                }
+            }
+            Some(Err(e)) => warn!("Unexpected media player message: {e}"),
+            None => return Err(anyhow!("Media player signals are closed")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use regex::Regex;
+    use rstest::rstest;
+    use zbus::zvariant::Value;
+
+    #[rstest]
+    #[case("org.mpris.MediaPlayer2.vlc", Some("vlc"))]
+    #[case("org.mpris.MediaPlayer2.firefox.instance_1_42", Some("firefox"))]
+    #[case("org.mpris.MediaPlayer2.chromium.instance2461", Some("chromium"))]
+    #[case("org.freedesktop.Notifications", None)]
+    fn names_players_by_bus(#[case] bus_name: &str, #[case] name: Option<&str>) {
+        assert_eq!(name, bus_player_name(bus_name));
+    }
+
+    #[rstest]
+    fn detects_player_changes() {
+        let properties = |interface: &str, status: &str| {
+            let changed = HashMap::from([("PlaybackStatus", Value::from(status))]);
+            Message::signal(
+                MPRIS_PATH,
+                "org.freedesktop.DBus.Properties",
+                "PropertiesChanged",
+            )
+            .unwrap()
+            .sender(":1.42")
+            .unwrap()
+            .build(&(interface, changed, Vec::<String>::new()))
+            .unwrap()
+        };
+        assert!(matches!(
+            player_change(&properties(PLAYER_INTERFACE, "Playing")),
+            Some(PlayerChange::Playback(sender, true)) if sender == ":1.42"
+        ));
+        assert!(matches!(
+            player_change(&properties(PLAYER_INTERFACE, "Paused")),
+            Some(PlayerChange::Playback(_, false))
+        ));
+        assert!(player_change(&properties("org.mpris.MediaPlayer2", "Playing")).is_none());
+
+        let owner = |name: &str| {
+            Message::signal(
+                "/org/freedesktop/DBus",
+                "org.freedesktop.DBus",
+                "NameOwnerChanged",
+            )
+            .unwrap()
+            .build(&(name, "", ":1.42"))
+            .unwrap()
+        };
+        assert!(matches!(
+            player_change(&owner("org.mpris.MediaPlayer2.vlc")),
+            Some(PlayerChange::Owner(name, _, new_owner)) if name == "org.mpris.MediaPlayer2.vlc" && new_owner == ":1.42"
+        ));
+        assert!(player_change(&owner("org.freedesktop.Notifications")).is_none());
+    }
+
+    #[rstest]
+    fn filters_playing_players() {
+        let player = |name: &str, is_playing| Player {
+            name: Some(name.to_string()),
+            is_playing,
+        };
+        let mut players = HashMap::from([
+            (":1.1".to_string(), player("firefox", true)),
+            (":1.2".to_string(), player("vlc", false)),
+        ]);
+        let allowed = MediaPlayers::new(vec![], vec![Regex::new("^firefox$").unwrap()], false);
+        assert!(!is_playing(&players, &allowed));
+
+        players.get_mut(":1.2").unwrap().is_playing = true;
+        assert!(is_playing(&players, &allowed));
+    }
+}
@@ -0,0 +1,282 @@
+/*
+ * niri sends the complete state of windows and workspaces as JSON lines after the EventStream request,
+ * and then only the changes, so the state is kept here and updated by the events.
+ * https://github.com/YaLTeR/niri/wiki/IPC
+ */
+use super::Watcher;
+use crate::report_client::ReportClient;
+use anyhow::{anyhow, Context};
+use async_trait::async_trait;
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::env;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::net::UnixStream;
+use tokio::time::{timeout, Duration};
+
+const EVENTS: &[&str] = &[
+    "WindowsChanged",
+    "WindowOpenedOrChanged",
+    "WindowClosed",
+    "WindowFocusChanged",
+    "WorkspacesChanged",
+    "WorkspaceActivated",
+];
+
+#[derive(Deserialize)]
+struct Window {
+    id: u64,
+    title: Option<String>,
+    app_id: Option<String>,
+    pid: Option<i32>,
+    workspace_id: Option<u64>,
+    is_focused: bool,
+}
+
+#[derive(Deserialize)]
+struct Workspace {
+    id: u64,
+    idx: u8,
+    name: Option<String>,
+    output: Option<String>,
+}
+
+#[derive(Deserialize)]
+enum Event {
+    WindowsChanged { windows: Vec<Window> },
+    WindowOpenedOrChanged { window: Window },
+    WindowClosed { id: u64 },
+    WindowFocusChanged { id: Option<u64> },
+    WorkspacesChanged { workspaces: Vec<Workspace> },
+    // Only wakes the watcher, the focused window is changed by WindowFocusChanged
+    WorkspaceActivated {},
+}
+
+fn parse_event(line: &str) -> anyhow::Result<Option<Event>> {
+    let value: Value = serde_json::from_str(line)?;
+    let name = value
+        .as_object()
+        .and_then(|event| event.keys().next())
+        .ok_or_else(|| anyhow!("Unexpected niri event: {line}"))?;
+    if !EVENTS.contains(&name.as_str()) {
+        return Ok(None);
+    }
+
+    Ok(Some(serde_json::from_value(value)?))
+}
+
+struct WindowData {
+    app_id: String,
+    title: String,
+    extra: HashMap<String, String>,
+}
+
+#[derive(Default)]
+struct NiriState {
+    windows: HashMap<u64, Window>,
+    workspaces: HashMap<u64, Workspace>,
+    focused_window_id: Option<u64>,
+}
+
+impl NiriState {
+    fn apply(&mut self, event: Event) {
+        match event {
+            Event::WindowsChanged { windows } => {
+                self.focused_window_id = windows
+                    .iter()
+                    .find(|window| window.is_focused)
+                    .map(|window| window.id);
+                self.windows = windows
+                    .into_iter()
+                    .map(|window| (window.id, window))
+                    .collect();
+            }
+            Event::WindowOpenedOrChanged { window } => {
+                if window.is_focused {
+                    self.focused_window_id = Some(window.id);
+                }
+                self.windows.insert(window.id, window);
+            }
+            Event::WindowClosed { id } => {
+                self.windows.remove(&id);
+                if self.focused_window_id == Some(id) {
+                    self.focused_window_id = None;
+                }
+            }
+            Event::WindowFocusChanged { id } => self.focused_window_id = id,
+            Event::WorkspacesChanged { workspaces } => {
+                self.workspaces = workspaces
+                    .into_iter()
+                    .map(|workspace| (workspace.id, workspace))
+                    .collect();
+            }
+            Event::WorkspaceActivated {} => (),
+        }
+    }
+
+    fn active_window(&self) -> Option<WindowData> {
+        let window = self.windows.get(&self.focused_window_id?)?;
+
+        let mut extra = HashMap::new();
+        if let Some(workspace) = window.workspace_id.and_then(|id| self.workspaces.get(&id)) {
+            let name = workspace
+                .name
+                .clone()
+                .unwrap_or_else(|| workspace.idx.to_string());
+            extra.insert("workspace".to_string(), name);
+            if let Some(output) = &workspace.output {
+                extra.insert("output".to_string(), output.clone());
+            }
+        }
+        if let Some(pid) = window.pid {
+            extra.insert("pid".to_string(), pid.to_string());
+        }
+
+        Some(WindowData {
+            app_id: window.app_id.clone().unwrap_or_default(),
+            title: window.title.clone().unwrap_or_default(),
+            extra,
+        })
+    }
+}
+
+pub struct WindowWatcher {
+    events: Lines<BufReader<UnixStream>>,
+    state: NiriState,
+}
+
+impl WindowWatcher {
+    async fn connect(path: &Path) -> anyhow::Result<Self> {
+        let mut stream = UnixStream::connect(path)
+            .await
+            .with_context(|| format!("Failed to connect to {}", path.display()))?;
+        stream.write_all(b"\"EventStream\"\n").await?;
+
+        let mut events = BufReader::new(stream).lines();
+        let reply = events
+            .next_line()
+            .await?
+            .ok_or_else(|| anyhow!("niri socket is closed"))?;
+        if !reply.starts_with(r#"{"Ok""#) {
+            return Err(anyhow!("niri refused the event stream: {reply}"));
+        }
+
+        let mut watcher = Self {
+            events,
+            state: NiriState::default(),
+        };
+        // The initial state is sent right away, windows are after workspaces
+        timeout(Duration::from_secs(5), async {
+            loop {
+                let event = watcher.next_event().await?;
+                let is_complete = matches!(event, Event::WindowsChanged { .. });
+                watcher.state.apply(event);
+                if is_complete {
+                    break anyhow::Ok(());
+                }
+            }
+        })
+        .await
+        .map_err(|_| anyhow!("niri didn't send the windows"))??;
+
+        Ok(watcher)
+    }
+
+    // Lines::next_line is cancel safe, a partially read line is kept for the next call
+    async fn next_event(&mut self) -> anyhow::Result<Event> {
+        while let Some(line) = self.events.next_line().await? {
+            if let Some(event) = parse_event(&line)? {
+                return Ok(event);
+            }
+        }
+        Err(anyhow!("niri event stream is closed"))
+    }
+}
+
+#[async_trait]
+impl Watcher for WindowWatcher {
+    async fn new(_: &Arc<ReportClient>) -> anyhow::Result<Self> {
+        let path = env::var("NIRI_SOCKET").map_err(|_| anyhow!("NIRI_SOCKET is not set"))?;
+
+        Self::connect(Path::new(&path)).await
+    }
+
+    async fn run_iteration(&mut self, client: &Arc<ReportClient>) -> anyhow::Result<()> {
+        if let Some(window) = self.state.active_window() {
+            client
+                .send_active_window_with_extra(&window.app_id, &window.title, Some(window.extra))
+                .await
+                .with_context(|| "Failed to send heartbeat for active window")
+        } else {
+            info!("No window is focused, skipping sending heartbeat");
+            Ok(())
+        }
+    }
+
+    async fn wait_for_event(&mut self) -> anyhow::Result<()> {
+        let event = self.next_event().await?;
+        self.state.apply(event);
+
+        Ok(())
+    }
+
+    fn is_event_driven(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use tokio::net::UnixListener;
+
+    const STREAM: &str = r#"{"Ok":"Handled"}
+{"WorkspacesChanged":{"workspaces":[
+    {"id":1,"idx":1,"name":null,"output":"eDP-1","is_active":true,"is_focused":true,"active_window_id":2},
+    {"id":2,"idx":2,"name":"chat","output":"HDMI-A-1","is_active":true,"is_focused":false,"active_window_id":1}]}}
+{"KeyboardLayoutsChanged":{"keyboard_layouts":{"names":["English (US)"],"current_idx":0}}}
+{"WindowsChanged":{"windows":[
+    {"id":1,"title":"Chat","app_id":"org.telegram.desktop","pid":100,"workspace_id":2,"is_focused":false,"is_floating":false},
+    {"id":2,"title":"vim","app_id":"foot","pid":200,"workspace_id":1,"is_focused":true,"is_floating":false}]}}
+{"WindowFocusChanged":{"id":1}}
+{"OverviewOpenedOrClosed":{"is_open":true}}
+{"WindowClosed":{"id":1}}
+"#;
+
+    #[rstest]
+    #[tokio::test]
+    async fn follows_event_stream() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("niri.sock");
+        let listener = UnixListener::bind(&path).unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            // One JSON line per event
+            let events = STREAM.replace("[\n", "[").replace(",\n", ",");
+            stream.write_all(events.as_bytes()).await.unwrap();
+        });
+
+        let mut watcher = WindowWatcher::connect(&path).await.unwrap();
+        let window = watcher.state.active_window().unwrap();
+        assert_eq!("foot", window.app_id);
+        assert_eq!("vim", window.title);
+        assert_eq!("1", window.extra["workspace"]);
+        assert_eq!("eDP-1", window.extra["output"]);
+        assert_eq!("200", window.extra["pid"]);
+
+        watcher.wait_for_event().await.unwrap();
+        let window = watcher.state.active_window().unwrap();
+        assert_eq!("org.telegram.desktop", window.app_id);
+        assert_eq!("chat", window.extra["workspace"]);
+        assert_eq!("HDMI-A-1", window.extra["output"]);
+
+        watcher.wait_for_event().await.unwrap();
+        assert!(watcher.state.active_window().is_none());
+
+        assert!(watcher.wait_for_event().await.is_err());
+    }
+}
@@ -0,0 +1,184 @@
+/*
+ * logind reports the lock by LockedHint of the session, which is set by the screen lockers,
+ * and requests it by Lock and Unlock signals (loginctl lock-session).
+ * Screen savers report it by ActiveChanged of org.freedesktop.ScreenSaver.
+ * Any of the sources is enough, the repeated changes are ignored by the client.
+ */
+use crate::report_client::ReportClient;
+use anyhow::anyhow;
+use futures_util::StreamExt;
+use std::collections::HashMap;
+use std::future::pending;
+use std::sync::Arc;
+use zbus::message::Type as MessageType;
+use zbus::zvariant::{OwnedObjectPath, OwnedValue};
+use zbus::{Connection, MatchRule, Message, MessageStream, Proxy};
+
+const LOGIND: &str = "org.freedesktop.login1";
+const LOGIND_SESSION: &str = "org.freedesktop.login1.Session";
+const SCREEN_SAVER: &str = "org.freedesktop.ScreenSaver";
+
+// Whether the message locks or unlocks the screen
+fn lock_change(message: &Message) -> Option<bool> {
+    let header = message.header();
+    let interface = header.interface()?.as_str();
+    let member = header.member()?.as_str();
+
+    match (interface, member) {
+        ("org.freedesktop.DBus.Properties", "PropertiesChanged") => {
+            let (interface, changed, _): (String, HashMap<String, OwnedValue>, Vec<String>) =
+                message.body().deserialize().ok()?;
+            if interface != LOGIND_SESSION {
+                return None;
+            }
+            bool::try_from(changed.get("LockedHint")?).ok()
+        }
+        (LOGIND_SESSION, "Lock") => Some(true),
+        (LOGIND_SESSION, "Unlock") => Some(false),
+        (SCREEN_SAVER, "ActiveChanged") => message.body().deserialize().ok(),
+        _ => None,
+    }
+}
+
+async fn next_change(stream: &mut Option<MessageStream>) -> Option<bool> {
+    let Some(messages) = stream else {
+        return pending().await;
+    };
+    loop {
+        match messages.next().await {
+            Some(Ok(message)) => {
+                if let Some(is_locked) = lock_change(&message) {
+                    return Some(is_locked);
+                }
+            }
+            Some(Err(e)) => warn!("Unexpected screen lock message: {e}"),
+            None => {
+                *stream = None;
+                return None;
+            }
+        }
+    }
+}
+
+// Returns the messages of the session and whether it is locked now
+async fn logind_messages() -> anyhow::Result<(MessageStream, bool)> {
+    let connection = Connection::system().await?;
+    // "auto" is the session of the process, or the graphical session of the user for services
+    let auto_session = Proxy::new(
+        &connection,
+        LOGIND,
+        "/org/freedesktop/login1/session/auto",
+        LOGIND_SESSION,
+    )
+    .await?;
+    let id: String = auto_session.get_property("Id").await?;
+    // Signals are sent from the real path of the session
+    let manager = Proxy::new(
+        &connection,
+        LOGIND,
+        "/org/freedesktop/login1",
+        "org.freedesktop.login1.Manager",
+    )
+    .await?;
+    let path: OwnedObjectPath = manager.call("GetSession", &id).await?;
+
+    let rule = MatchRule::builder()
+        .msg_type(MessageType::Signal)
+        .sender(LOGIND)?
+        .path(path.clone())?
+        .build();
+    let messages = MessageStream::for_match_rule(rule, &connection, None).await?;
+
+    let session = Proxy::new(&connection, LOGIND, path, LOGIND_SESSION).await?;
+    let is_locked = session.get_property("LockedHint").await?;
+    debug!("Watching the lock of logind session {id}");
+
+    Ok((messages, is_locked))
+}
+
+async fn screen_saver_messages() -> anyhow::Result<MessageStream> {
+    let connection = Connection::session().await?;
+    // KDE sends it from 2 paths, GNOME has it only with org.gnome.ScreenSaver
+    let rule = MatchRule::builder()
+        .msg_type(MessageType::Signal)
+        .interface(SCREEN_SAVER)?
+        .member("ActiveChanged")?
+        .build();
+
+    Ok(MessageStream::for_match_rule(rule, &connection, None).await?)
+}
+
+/// Reports the screen lock to the client until all the sources are gone.
+pub async fn run_screen_lock_watcher(client: Arc<ReportClient>) -> anyhow::Result<()> {
+    let mut logind = match logind_messages().await {
+        Ok((messages, is_locked)) => {
+            if is_locked {
+                client.set_locked(true, client.clock.now()).await?;
+            }
+            Some(messages)
+        }
+        Err(e) => {
+            warn!("Failed to watch the logind session lock: {e}");
+            None
+        }
+    };
+    let mut screen_saver = match screen_saver_messages().await {
+        Ok(messages) => Some(messages),
+        Err(e) => {
+            warn!("Failed to watch the screen saver: {e}");
+            None
+        }
+    };
+
+    while logind.is_some() || screen_saver.is_some() {
+        let change = tokio::select! {
+            change = next_change(&mut logind) => change,
+            change = next_change(&mut screen_saver) => change,
+        };
+        if let Some(is_locked) = change {
+            if let Err(e) = client.set_locked(is_locked, client.clock.now()).await {
+                error!("Error on reporting the screen lock: {e:?}");
+            }
+        }
+    }
+
+    Err(anyhow!("No screen lock source is available"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use zbus::zvariant::Value;
+
+    fn signal(interface: &'static str, member: &'static str) -> zbus::message::Builder<'static> {
+        Message::signal("/org/freedesktop/login1/session/_32", interface, member).unwrap()
+    }
+
+    #[rstest]
+    fn detects_lock_changes() {
+        let properties = |interface: &'static str, name: &str| {
+            let changed = HashMap::from([(name, Value::from(true))]);
+            signal("org.freedesktop.DBus.Properties", "PropertiesChanged")
+                .build(&(interface, changed, Vec::<String>::new()))
+                .unwrap()
+        };
+
+        assert_eq!(
+            Some(true),
+            lock_change(&properties(LOGIND_SESSION, "LockedHint"))
+        );
+        assert_eq!(None, lock_change(&properties(LOGIND_SESSION, "IdleHint")));
+        assert_eq!(None, lock_change(&properties("org.other", "LockedHint")));
+
+        let lock = signal(LOGIND_SESSION, "Lock").build(&()).unwrap();
+        assert_eq!(Some(true), lock_change(&lock));
+        let unlock = signal(LOGIND_SESSION, "Unlock").build(&()).unwrap();
+        assert_eq!(Some(false), lock_change(&unlock));
+
+        let screen_saver = signal(SCREEN_SAVER, "ActiveChanged")
+            .build(&(false,))
+            .unwrap();
+        assert_eq!(Some(false), lock_change(&screen_saver));
+    }
+}
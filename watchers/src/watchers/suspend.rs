@@ -0,0 +1,70 @@
+/*
+ * logind sends PrepareForSleep(true) before the suspend and PrepareForSleep(false) after the resume.
+ * A delay inhibitor lets the suspend wait until the idle event is delivered, logind waits for it
+ * up to InhibitDelayMaxSec (5 seconds by default).
+ * https://systemd.io/INHIBITOR_LOCKS/
+ */
+use crate::report_client::ReportClient;
+use anyhow::anyhow;
+use futures_util::StreamExt;
+use std::sync::Arc;
+use std::time::Duration;
+use zbus::zvariant::OwnedFd;
+use zbus::{Connection, Proxy};
+
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(3);
+
+async fn inhibit(manager: &Proxy<'_>) -> Option<OwnedFd> {
+    let inhibitor = manager
+        .call(
+            "Inhibit",
+            &(
+                "sleep",
+                "awatcher",
+                "Reporting the suspend as idle",
+                "delay",
+            ),
+        )
+        .await;
+    match inhibitor {
+        Ok(inhibitor) => Some(inhibitor),
+        Err(e) => {
+            warn!("Failed to delay the suspend: {e}");
+            None
+        }
+    }
+}
+
+/// Reports the suspend and resume to the client while logind is available.
+pub async fn run_suspend_watcher(client: Arc<ReportClient>) -> anyhow::Result<()> {
+    let connection = Connection::system().await?;
+    let manager = Proxy::new(
+        &connection,
+        "org.freedesktop.login1",
+        "/org/freedesktop/login1",
+        "org.freedesktop.login1.Manager",
+    )
+    .await?;
+    let mut signals = manager.receive_signal("PrepareForSleep").await?;
+    let mut inhibitor = inhibit(&manager).await;
+
+    while let Some(message) = signals.next().await {
+        let is_suspended: bool = message.body().deserialize()?;
+        if let Err(e) = client.set_suspended(is_suspended, client.clock.now()).await {
+            error!("Error on reporting the suspend: {e:?}");
+        }
+
+        if is_suspended {
+            // The undelivered heartbeats are spooled on timeout
+            if let Err(e) = client.flush(FLUSH_TIMEOUT).await {
+                error!("Failed to deliver the events before the suspend: {e:?}");
+            }
+            // Releasing the inhibitor lets the system suspend
+            inhibitor = None;
+        } else if inhibitor.is_none() {
+            inhibitor = inhibit(&manager).await;
+        }
+    }
+
+    Err(anyhow!("logind suspend signals are closed"))
+}
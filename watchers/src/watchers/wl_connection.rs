@@ -1,5 +1,9 @@
 use anyhow::Context;
+use std::io::ErrorKind;
+use std::os::fd::AsRawFd;
+use tokio::io::{unix::AsyncFd, Interest};
 use wayland_client::{
+    backend::WaylandError,
     globals::{registry_queue_init, GlobalList, GlobalListContents},
     protocol::{wl_registry, wl_seat::WlSeat},
     Connection, Dispatch, EventQueue, Proxy, QueueHandle,
@@ -13,6 +17,7 @@ use wayland_protocols::ext::idle_notify::v1::client::{
 use wayland_protocols_plasma::idle::client::{
     org_kde_kwin_idle::OrgKdeKwinIdle, org_kde_kwin_idle_timeout::OrgKdeKwinIdleTimeout,
 };
+use wayland_protocols_plasma::plasma_window_management::client::org_kde_plasma_window_management::OrgKdePlasmaWindowManagement;
 use wayland_protocols_wlr::foreign_toplevel::v1::client::zwlr_foreign_toplevel_manager_v1::ZwlrForeignToplevelManagerV1;
 
 macro_rules! subscribe_state {
@@ -68,6 +73,29 @@ where
             .map(|_| ())
     }
 
+    /// Waits until the compositor sends events and dispatches them to the state.
+    pub async fn dispatch_events(&mut self, state: &mut T) -> anyhow::Result<()> {
+        if self.event_queue.dispatch_pending(state)? > 0 {
+            return Ok(());
+        }
+        self.event_queue.flush()?;
+
+        if let Some(guard) = self.event_queue.prepare_read() {
+            let fd = AsyncFd::with_interest(guard.connection_fd().as_raw_fd(), Interest::READABLE)?;
+            fd.readable().await?.clear_ready();
+
+            match guard.read() {
+                Err(WaylandError::Io(e)) if e.kind() == ErrorKind::WouldBlock => {}
+                result => {
+                    result?;
+                }
+            }
+        }
+        self.event_queue.dispatch_pending(state)?;
+
+        Ok(())
+    }
+
     pub fn get_foreign_toplevel_manager(&self) -> anyhow::Result<ZwlrForeignToplevelManagerV1>
     where
         T: Dispatch<ZwlrForeignToplevelManagerV1, ()>,
@@ -148,6 +176,20 @@ where
             .map_err(std::convert::Into::into)
     }
 
+    pub fn get_plasma_window_management(&self) -> anyhow::Result<OrgKdePlasmaWindowManagement>
+    where
+        T: Dispatch<OrgKdePlasmaWindowManagement, ()>,
+    {
+        // Windows are announced by IDs until the version 13 which uses UUIDs
+        self.globals
+            .bind::<OrgKdePlasmaWindowManagement, T, ()>(
+                &self.queue_handle,
+                1..=OrgKdePlasmaWindowManagement::interface().version.min(12),
+                (),
+            )
+            .map_err(std::convert::Into::into)
+    }
+
     pub fn get_cosmic_toplevel_info_v2(&self) -> anyhow::Result<ZcosmicToplevelInfoV1>
     where
         T: Dispatch<ZcosmicToplevelInfoV1, ()>,
@@ -0,0 +1,364 @@
+/*
+ * ext-foreign-toplevel-list-v1 only lists the windows, the active one is known from another protocol
+ * offered by the compositor: wlr-foreign-toplevel-management or KDE Plasma window management.
+ * The windows of both protocols are matched by their app ID and title, the list has the latest titles.
+ * Without wlr it's the only Wayland toplevel backend for the compositors with Plasma window management,
+ * e.g. KWin when the script cannot be loaded.
+ */
+use super::wl_connection::WlEventConnection;
+use super::{wl_connection::subscribe_state, Watcher};
+use crate::report_client::ReportClient;
+use anyhow::{anyhow, Context};
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::Arc;
+use wayland_client::{
+    event_created_child, globals::GlobalListContents, protocol::wl_registry, Connection, Dispatch,
+    Proxy, QueueHandle,
+};
+use wayland_protocols::ext::foreign_toplevel_list::v1::client::ext_foreign_toplevel_handle_v1::{
+    Event as ForeignHandleEvent, ExtForeignToplevelHandleV1,
+};
+use wayland_protocols::ext::foreign_toplevel_list::v1::client::ext_foreign_toplevel_list_v1::{
+    Event as ForeignListEvent, ExtForeignToplevelListV1, EVT_TOPLEVEL_OPCODE,
+};
+use wayland_protocols_plasma::plasma_window_management::client::org_kde_plasma_window::{
+    Event as PlasmaWindowEvent, OrgKdePlasmaWindow,
+};
+use wayland_protocols_plasma::plasma_window_management::client::org_kde_plasma_window_management::{
+    Event as PlasmaManagementEvent, OrgKdePlasmaWindowManagement, State as PlasmaState,
+};
+use wayland_protocols_wlr::foreign_toplevel::v1::client::zwlr_foreign_toplevel_handle_v1::{
+    Event as WlrHandleEvent, State as WlrHandleState, ZwlrForeignToplevelHandleV1,
+};
+use wayland_protocols_wlr::foreign_toplevel::v1::client::zwlr_foreign_toplevel_manager_v1::{
+    Event as WlrManagerEvent, ZwlrForeignToplevelManagerV1,
+    EVT_TOPLEVEL_OPCODE as WLR_EVT_TOPLEVEL_OPCODE,
+};
+
+#[derive(Default)]
+struct WindowData {
+    app_id: String,
+    title: String,
+}
+
+#[derive(Default)]
+struct ToplevelState {
+    // Windows of ext-foreign-toplevel-list by the handle ID
+    windows: HashMap<String, WindowData>,
+    // Windows of the focus source by the handle ID
+    focus_windows: HashMap<String, WindowData>,
+    focused_window_id: Option<String>,
+}
+
+impl ToplevelState {
+    fn get_active_window(&self) -> Option<&WindowData> {
+        let focused = self.focus_windows.get(self.focused_window_id.as_ref()?)?;
+        let app_windows: Vec<&WindowData> = self
+            .windows
+            .values()
+            .filter(|window| window.app_id == focused.app_id)
+            .collect();
+
+        match app_windows.as_slice() {
+            // The focus source may lag behind the list on title changes
+            [window] => Some(window),
+            windows => windows
+                .iter()
+                .find(|window| window.title == focused.title)
+                .copied()
+                .or(Some(focused)),
+        }
+    }
+
+    fn set_focused(&mut self, id: String, is_focused: bool) {
+        if is_focused {
+            trace!("Window is activated: {id}");
+            self.focused_window_id = Some(id);
+        } else if self.focused_window_id.as_ref() == Some(&id) {
+            self.focused_window_id = None;
+        }
+    }
+
+    fn remove_focus_window(&mut self, id: &str) {
+        self.focus_windows.remove(id);
+        if self.focused_window_id.as_deref() == Some(id) {
+            self.focused_window_id = None;
+        }
+    }
+}
+
+impl Dispatch<ExtForeignToplevelListV1, ()> for ToplevelState {
+    fn event(
+        state: &mut Self,
+        _: &ExtForeignToplevelListV1,
+        event: <ExtForeignToplevelListV1 as Proxy>::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        match event {
+            ForeignListEvent::Toplevel { toplevel } => {
+                let id = toplevel.id().to_string();
+                debug!("Foreign toplevel handle is received {id}");
+                state.windows.insert(id, WindowData::default());
+            }
+            ForeignListEvent::Finished => {
+                error!("Foreign toplevel list is finished, no more windows will be reported");
+            }
+            _ => (),
+        }
+    }
+
+    event_created_child!(ToplevelState, ExtForeignToplevelListV1, [
+        EVT_TOPLEVEL_OPCODE => (ExtForeignToplevelHandleV1, ()),
+    ]);
+}
+
+impl Dispatch<ExtForeignToplevelHandleV1, ()> for ToplevelState {
+    fn event(
+        state: &mut Self,
+        handle: &ExtForeignToplevelHandleV1,
+        event: <ExtForeignToplevelHandleV1 as Proxy>::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        let id = handle.id().to_string();
+        match event {
+            ForeignHandleEvent::Closed => {
+                trace!("Window is closed: {id}");
+                state.windows.remove(&id);
+            }
+            event => {
+                let Some(window) = state.windows.get_mut(&id) else {
+                    error!("Window is not found: {id}");
+                    return;
+                };
+                match event {
+                    ForeignHandleEvent::Title { title } => window.title = title,
+                    ForeignHandleEvent::AppId { app_id } => window.app_id = app_id,
+                    _ => (),
+                }
+            }
+        }
+    }
+}
+
+impl Dispatch<ZwlrForeignToplevelManagerV1, ()> for ToplevelState {
+    fn event(
+        state: &mut Self,
+        _: &ZwlrForeignToplevelManagerV1,
+        event: <ZwlrForeignToplevelManagerV1 as Proxy>::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        if let WlrManagerEvent::Toplevel { toplevel } = event {
+            state
+                .focus_windows
+                .insert(toplevel.id().to_string(), WindowData::default());
+        }
+    }
+
+    event_created_child!(ToplevelState, ZwlrForeignToplevelManagerV1, [
+        WLR_EVT_TOPLEVEL_OPCODE => (ZwlrForeignToplevelHandleV1, ()),
+    ]);
+}
+
+impl Dispatch<ZwlrForeignToplevelHandleV1, ()> for ToplevelState {
+    fn event(
+        state: &mut Self,
+        handle: &ZwlrForeignToplevelHandleV1,
+        event: <ZwlrForeignToplevelHandleV1 as Proxy>::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        let id = handle.id().to_string();
+        match event {
+            WlrHandleEvent::State {
+                state: handle_state,
+            } => {
+                let is_activated = handle_state.contains(&(WlrHandleState::Activated as u8));
+                state.set_focused(id, is_activated);
+            }
+            WlrHandleEvent::Closed => state.remove_focus_window(&id),
+            event => {
+                if let Some(window) = state.focus_windows.get_mut(&id) {
+                    match event {
+                        WlrHandleEvent::Title { title } => window.title = title,
+                        WlrHandleEvent::AppId { app_id } => window.app_id = app_id,
+                        _ => (),
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Dispatch<OrgKdePlasmaWindowManagement, ()> for ToplevelState {
+    fn event(
+        state: &mut Self,
+        management: &OrgKdePlasmaWindowManagement,
+        event: <OrgKdePlasmaWindowManagement as Proxy>::Event,
+        _: &(),
+        _: &Connection,
+        queue_handle: &QueueHandle<Self>,
+    ) {
+        if let PlasmaManagementEvent::Window { id } = event {
+            let window = management.get_window(id, queue_handle, ());
+            state
+                .focus_windows
+                .insert(window.id().to_string(), WindowData::default());
+        }
+    }
+}
+
+impl Dispatch<OrgKdePlasmaWindow, ()> for ToplevelState {
+    fn event(
+        state: &mut Self,
+        window: &OrgKdePlasmaWindow,
+        event: <OrgKdePlasmaWindow as Proxy>::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        let id = window.id().to_string();
+        match event {
+            PlasmaWindowEvent::StateChanged { flags } => {
+                state.set_focused(id, flags & PlasmaState::Active as u32 != 0);
+            }
+            PlasmaWindowEvent::Unmapped => state.remove_focus_window(&id),
+            event => {
+                if let Some(window) = state.focus_windows.get_mut(&id) {
+                    match event {
+                        PlasmaWindowEvent::TitleChanged { title } => window.title = title,
+                        PlasmaWindowEvent::AppIdChanged { app_id } => window.app_id = app_id,
+                        _ => (),
+                    }
+                }
+            }
+        }
+    }
+}
+
+subscribe_state!(wl_registry::WlRegistry, GlobalListContents, ToplevelState);
+subscribe_state!(wl_registry::WlRegistry, (), ToplevelState);
+
+pub struct WindowWatcher {
+    connection: WlEventConnection<ToplevelState>,
+    toplevel_state: ToplevelState,
+}
+
+impl WindowWatcher {
+    async fn send_active_window(&self, client: &Arc<ReportClient>) -> anyhow::Result<()> {
+        if let Some(active_window) = self.toplevel_state.get_active_window() {
+            client
+                .send_active_window(&active_window.app_id, &active_window.title)
+                .await
+                .with_context(|| "Failed to send heartbeat for active window")
+        } else {
+            info!("Current active window is unknown, skipping sending heartbeat");
+            Ok(())
+        }
+    }
+}
+
+#[async_trait]
+impl Watcher for WindowWatcher {
+    async fn new(_: &Arc<ReportClient>) -> anyhow::Result<Self> {
+        let mut connection: WlEventConnection<ToplevelState> = WlEventConnection::connect()?;
+        connection.get_ext_foreign_toplevel_list()?;
+
+        if connection.get_foreign_toplevel_manager().is_ok() {
+            debug!("Focus source: wlr-foreign-toplevel-management");
+        } else if connection.get_plasma_window_management().is_ok() {
+            debug!("Focus source: KDE Plasma window management");
+        } else {
+            return Err(anyhow!(
+                "No focus source for ext-foreign-toplevel-list, the active window is unknown"
+            ));
+        }
+
+        let mut toplevel_state = ToplevelState::default();
+        // The second roundtrip receives the properties of the windows created on the first one
+        for _ in 0..2 {
+            connection
+                .roundtrip(&mut toplevel_state)
+                .map_err(|e| anyhow!("Event queue is not processed: {e}"))?;
+        }
+
+        Ok(Self {
+            connection,
+            toplevel_state,
+        })
+    }
+
+    async fn run_iteration(&mut self, client: &Arc<ReportClient>) -> anyhow::Result<()> {
+        self.connection
+            .roundtrip(&mut self.toplevel_state)
+            .map_err(|e| anyhow!("Event queue is not processed: {e}"))?;
+
+        self.send_active_window(client).await
+    }
+
+    async fn wait_for_event(&mut self) -> anyhow::Result<()> {
+        self.connection
+            .dispatch_events(&mut self.toplevel_state)
+            .await
+    }
+
+    fn is_event_driven(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    fn window(app_id: &str, title: &str) -> WindowData {
+        WindowData {
+            app_id: app_id.to_string(),
+            title: title.to_string(),
+        }
+    }
+
+    #[rstest]
+    fn matches_focused_window() {
+        let mut state = ToplevelState::default();
+        state
+            .windows
+            .insert("1".to_string(), window("firefox", "Mozilla Firefox"));
+        state
+            .windows
+            .insert("2".to_string(), window("code", "main.rs"));
+        state
+            .focus_windows
+            .insert("10".to_string(), window("code", "main.rs"));
+        assert!(state.get_active_window().is_none());
+
+        state.set_focused("10".to_string(), true);
+        let active = state.get_active_window().unwrap();
+        assert_eq!("code", active.app_id);
+        assert_eq!("main.rs", active.title);
+
+        // The list has a newer title than the focus source
+        state.windows.get_mut("2").unwrap().title = "lib.rs".to_string();
+        assert_eq!("lib.rs", state.get_active_window().unwrap().title);
+
+        // Several windows of the app are matched by the title
+        state
+            .windows
+            .insert("3".to_string(), window("code", "main.rs"));
+        assert_eq!("main.rs", state.get_active_window().unwrap().title);
+
+        state.set_focused("11".to_string(), false);
+        assert!(state.get_active_window().is_some());
+
+        state.remove_focus_window("10");
+        assert!(state.get_active_window().is_none());
+    }
+}
@@ -1,10 +1,11 @@
 use super::idle;
 use super::wl_connection::{subscribe_state, WlEventConnection};
 use super::Watcher;
+use crate::clock::Clock;
 use crate::report_client::ReportClient;
 use anyhow::anyhow;
 use async_trait::async_trait;
-use chrono::{TimeDelta, Utc};
+use chrono::TimeDelta;
 use std::sync::Arc;
 use wayland_client::{
     globals::GlobalListContents,
@@ -18,6 +19,7 @@ use wayland_protocols::ext::idle_notify::v1::client::ext_idle_notifier_v1::ExtId
 struct WatcherState {
     idle_notification: ExtIdleNotificationV1,
     idle_state: idle::Tracker,
+    clock: Arc<dyn Clock>,
 }
 
 impl Drop for WatcherState {
@@ -28,19 +30,24 @@ impl Drop for WatcherState {
 }
 
 impl WatcherState {
-    fn new(idle_notification: ExtIdleNotificationV1, idle_timeout: TimeDelta) -> Self {
+    fn new(
+        idle_notification: ExtIdleNotificationV1,
+        idle_timeout: TimeDelta,
+        clock: Arc<dyn Clock>,
+    ) -> Self {
         Self {
             idle_notification,
-            idle_state: idle::Tracker::new(Utc::now(), idle_timeout),
+            idle_state: idle::Tracker::new(clock.now(), idle_timeout),
+            clock,
         }
     }
 
     fn idle(&mut self) {
-        self.idle_state.mark_idle(Utc::now());
+        self.idle_state.mark_idle(self.clock.now());
     }
 
     fn resume(&mut self) {
-        self.idle_state.mark_not_idle(Utc::now());
+        self.idle_state.mark_not_idle(self.clock.now());
     }
 }
 
@@ -77,12 +84,14 @@ impl Watcher for IdleWatcher {
         let mut connection: WlEventConnection<WatcherState> = WlEventConnection::connect()?;
         connection.get_ext_idle()?;
 
-        let timeout = u32::try_from(client.config.idle_timeout.num_milliseconds());
+        let idle_timeout = client.config.shortest_idle_timeout();
+        let timeout = u32::try_from(idle_timeout.num_milliseconds());
         let mut watcher_state = WatcherState::new(
             connection
                 .get_ext_idle_notification(timeout.unwrap())
                 .unwrap(),
-            client.config.idle_timeout,
+            idle_timeout,
+            Arc::clone(&client.clock),
         );
         connection.roundtrip(&mut watcher_state).unwrap();
 
@@ -96,9 +105,21 @@ impl Watcher for IdleWatcher {
         self.connection
             .roundtrip(&mut self.watcher_state)
             .map_err(|e| anyhow!("Event queue is not processed: {e}"))?;
+        let idle_state = &mut self.watcher_state.idle_state;
+        idle_state.set_window_timeout(client.idle_timeout());
 
         client
-            .handle_idle_status(self.watcher_state.idle_state.get_reactive(Utc::now())?)
+            .handle_idle_status(idle_state.get_reactive(client.clock.now())?)
             .await
     }
+
+    async fn wait_for_event(&mut self) -> anyhow::Result<()> {
+        self.connection
+            .dispatch_events(&mut self.watcher_state)
+            .await
+    }
+
+    fn is_event_driven(&self) -> bool {
+        true
+    }
 }
@@ -167,4 +167,14 @@ impl Watcher for WindowWatcher {
 
         self.send_active_window(client).await
     }
+
+    async fn wait_for_event(&mut self) -> anyhow::Result<()> {
+        self.connection
+            .dispatch_events(&mut self.toplevel_state)
+            .await
+    }
+
+    fn is_event_driven(&self) -> bool {
+        true
+    }
 }
@@ -1,10 +1,11 @@
 use super::idle;
 use super::wl_connection::{subscribe_state, WlEventConnection};
 use super::Watcher;
+use crate::clock::Clock;
 use crate::report_client::ReportClient;
 use anyhow::anyhow;
 use async_trait::async_trait;
-use chrono::{TimeDelta, Utc};
+use chrono::TimeDelta;
 use std::sync::Arc;
 use wayland_client::{
     globals::GlobalListContents,
@@ -19,6 +20,7 @@ use wayland_protocols_plasma::idle::client::org_kde_kwin_idle_timeout::{
 struct WatcherState {
     kwin_idle_timeout: OrgKdeKwinIdleTimeout,
     idle_state: idle::Tracker,
+    clock: Arc<dyn Clock>,
 }
 
 impl Drop for WatcherState {
@@ -29,20 +31,25 @@ impl Drop for WatcherState {
 }
 
 impl WatcherState {
-    fn new(kwin_idle_timeout: OrgKdeKwinIdleTimeout, idle_timeout: TimeDelta) -> Self {
+    fn new(
+        kwin_idle_timeout: OrgKdeKwinIdleTimeout,
+        idle_timeout: TimeDelta,
+        clock: Arc<dyn Clock>,
+    ) -> Self {
         Self {
             kwin_idle_timeout,
-            idle_state: idle::Tracker::new(Utc::now(), idle_timeout),
+            idle_state: idle::Tracker::new(clock.now(), idle_timeout),
+            clock,
         }
     }
 
     fn idle(&mut self) {
-        let time = Utc::now();
+        let time = self.clock.now();
         self.idle_state.mark_idle(time);
     }
 
     fn resume(&mut self) {
-        let time = Utc::now();
+        let time = self.clock.now();
         self.idle_state.mark_not_idle(time);
     }
 }
@@ -80,10 +87,12 @@ impl Watcher for IdleWatcher {
         let mut connection: WlEventConnection<WatcherState> = WlEventConnection::connect()?;
         connection.get_kwin_idle()?;
 
-        let timeout = u32::try_from(client.config.idle_timeout.num_milliseconds());
+        let idle_timeout = client.config.shortest_idle_timeout();
+        let timeout = u32::try_from(idle_timeout.num_milliseconds());
         let mut watcher_state = WatcherState::new(
             connection.get_kwin_idle_timeout(timeout.unwrap()).unwrap(),
-            client.config.idle_timeout,
+            idle_timeout,
+            Arc::clone(&client.clock),
         );
         connection.roundtrip(&mut watcher_state).unwrap();
 
@@ -97,9 +106,21 @@ impl Watcher for IdleWatcher {
         self.connection
             .roundtrip(&mut self.watcher_state)
             .map_err(|e| anyhow!("Event queue is not processed: {e}"))?;
+        let idle_state = &mut self.watcher_state.idle_state;
+        idle_state.set_window_timeout(client.idle_timeout());
 
         client
-            .handle_idle_status(self.watcher_state.idle_state.get_reactive(Utc::now())?)
+            .handle_idle_status(idle_state.get_reactive(client.clock.now())?)
             .await
     }
+
+    async fn wait_for_event(&mut self) -> anyhow::Result<()> {
+        self.connection
+            .dispatch_events(&mut self.watcher_state)
+            .await
+    }
+
+    fn is_event_driven(&self) -> bool {
+        true
+    }
 }
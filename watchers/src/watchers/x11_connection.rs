@@ -1,20 +1,45 @@
 use anyhow::{anyhow, bail, Context};
 use log::warn;
+use std::os::fd::AsRawFd;
 use std::{env, str};
+use tokio::io::unix::AsyncFd;
+use tokio::io::Interest;
 use x11rb::connection::Connection;
 use x11rb::protocol::screensaver::ConnectionExt as ScreensaverConnectionExt;
-use x11rb::protocol::xproto::{Atom, AtomEnum, ConnectionExt, GetPropertyReply, Window};
+use x11rb::protocol::xproto::{
+    Atom, AtomEnum, ChangeWindowAttributesAux, ConnectionExt, EventMask, GetPropertyReply, Window,
+};
+use x11rb::protocol::Event;
 use x11rb::rust_connection::RustConnection;
 
+x11rb::atom_manager! {
+    Atoms: AtomsCookie {
+        _NET_ACTIVE_WINDOW,
+        _NET_WM_NAME,
+        _NET_WM_PID,
+        _NET_WM_WINDOW_TYPE,
+        WM_WINDOW_ROLE,
+        UTF8_STRING,
+    }
+}
+
+#[derive(Clone, PartialEq)]
 pub struct WindowData {
     pub title: String,
     pub app_id: String,
     pub wm_instance: String,
+    pub pid: Option<u32>,
+    pub role: Option<String>,
+    pub window_type: Option<String>,
 }
 
 pub struct X11Client {
     connection: RustConnection,
     screen_root: Window,
+    atoms: Atoms,
+    // Property changes of the root and the active window are selected
+    is_watching: bool,
+    watched_window: Option<Window>,
 }
 
 impl X11Client {
@@ -26,18 +51,35 @@ impl X11Client {
 
         let (connection, screen_num) = x11rb::connect(None)?;
         let screen_root = connection.setup().roots[screen_num].root;
+        let atoms = Atoms::new(&connection)?.reply()?;
 
         Ok(X11Client {
             connection,
             screen_root,
+            atoms,
+            is_watching: false,
+            watched_window: None,
         })
     }
 
     fn reconnect(&mut self) {
-        match x11rb::connect(None) {
-            Ok((connection, screen_num)) => {
+        let connected = x11rb::connect(None).map_err(anyhow::Error::from).and_then(
+            |(connection, screen_num)| {
+                let atoms = Atoms::new(&connection)?.reply()?;
+                Ok((connection, screen_num, atoms))
+            },
+        );
+        match connected {
+            Ok((connection, screen_num, atoms)) => {
                 self.screen_root = connection.setup().roots[screen_num].root;
                 self.connection = connection;
+                self.atoms = atoms;
+                self.watched_window = None;
+                if self.is_watching {
+                    if let Err(e) = self.select_property_changes(self.screen_root) {
+                        error!("Failed to watch the root window after reconnecting: {e}");
+                    }
+                }
             }
             Err(e) => error!("Failed to reconnect to X11: {e}"),
         };
@@ -67,15 +109,100 @@ impl X11Client {
         })
     }
 
+    /// Selects property changes of the root window, and of the active window on every query.
+    /// Returns false if the window manager doesn't maintain `_NET_ACTIVE_WINDOW`, so changes must be polled.
+    pub fn watch_active_window(&mut self) -> anyhow::Result<bool> {
+        let active_window = self.get_property(
+            self.screen_root,
+            self.atoms._NET_ACTIVE_WINDOW,
+            "_NET_ACTIVE_WINDOW",
+            AtomEnum::WINDOW.into(),
+            1,
+        )?;
+        if active_window.format != 32 {
+            return Ok(false);
+        }
+
+        self.select_property_changes(self.screen_root)?;
+        self.is_watching = true;
+        Ok(true)
+    }
+
+    /// Resolves on a change of the active window, or of its title or class.
+    pub async fn wait_for_change(&mut self) -> anyhow::Result<()> {
+        loop {
+            // Events may be already read along with replies
+            while let Some(event) = self.connection.poll_for_event()? {
+                if self.is_relevant_change(&event) {
+                    return Ok(());
+                }
+            }
+            self.connection.flush()?;
+
+            // poll_for_event reads the socket until it would block
+            let fd =
+                AsyncFd::with_interest(self.connection.stream().as_raw_fd(), Interest::READABLE)?;
+            fd.readable().await?.clear_ready();
+        }
+    }
+
+    fn is_relevant_change(&self, event: &Event) -> bool {
+        let Event::PropertyNotify(event) = event else {
+            return false;
+        };
+        if event.window == self.screen_root {
+            event.atom == self.atoms._NET_ACTIVE_WINDOW
+        } else {
+            Some(event.window) == self.watched_window
+                && [
+                    self.atoms._NET_WM_NAME,
+                    AtomEnum::WM_NAME.into(),
+                    AtomEnum::WM_CLASS.into(),
+                ]
+                .contains(&event.atom)
+        }
+    }
+
+    fn select_property_changes(&self, window: Window) -> anyhow::Result<()> {
+        self.connection
+            .change_window_attributes(
+                window,
+                &ChangeWindowAttributesAux::new().event_mask(EventMask::PROPERTY_CHANGE),
+            )
+            .with_context(|| "ChangeWindowAttributesRequest failed")?;
+        Ok(())
+    }
+
+    fn watch_window(&mut self, window: Window) {
+        if self.watched_window == Some(window) {
+            return;
+        }
+        // The previous window may be already destroyed, then the error arrives as an ignored event
+        if let Some(previous) = self
+            .watched_window
+            .filter(|&w| w != self.screen_root && w != x11rb::NONE)
+        {
+            let _ = self.connection.change_window_attributes(
+                previous,
+                &ChangeWindowAttributesAux::new().event_mask(EventMask::NO_EVENT),
+            );
+        }
+        self.watched_window = Some(window);
+    }
+
     pub fn active_window_data(&mut self) -> anyhow::Result<WindowData> {
-        self.execute_with_reconnect(|client| {
+        let (focus, data) = self.execute_with_reconnect(|client| {
             let focus: Window = client.find_active_window()?;
+            // Selected before reading the properties to not miss their changes
+            if client.is_watching && focus != client.screen_root && focus != x11rb::NONE {
+                client.select_property_changes(focus)?;
+            }
 
             let name = client.get_property(
                 focus,
-                client.intern_atom("_NET_WM_NAME")?,
+                client.atoms._NET_WM_NAME,
                 "_NET_WM_NAME",
-                client.intern_atom("UTF8_STRING")?,
+                client.atoms.UTF8_STRING,
                 u32::MAX,
             )?;
             let class = client.get_property(
@@ -89,12 +216,79 @@ impl X11Client {
             let title = str::from_utf8(&name.value).with_context(|| "Invalid title UTF")?;
             let (instance, class) = parse_wm_class(&class)?;
 
-            Ok(WindowData {
-                title: title.to_string(),
-                app_id: class,
-                wm_instance: instance,
-            })
-        })
+            Ok((
+                focus,
+                WindowData {
+                    title: title.to_string(),
+                    app_id: class,
+                    wm_instance: instance,
+                    pid: client.get_pid(focus)?,
+                    role: client.get_role(focus)?,
+                    window_type: client.get_window_type(focus)?,
+                },
+            ))
+        })?;
+        if self.is_watching {
+            self.watch_window(focus);
+        }
+
+        Ok(data)
+    }
+
+    fn get_pid(&self, window: Window) -> anyhow::Result<Option<u32>> {
+        let pid = self.get_property(
+            window,
+            self.atoms._NET_WM_PID,
+            "_NET_WM_PID",
+            AtomEnum::CARDINAL.into(),
+            1,
+        )?;
+
+        Ok(pid.value32().and_then(|mut value| value.next()))
+    }
+
+    fn get_role(&self, window: Window) -> anyhow::Result<Option<String>> {
+        let role = self.get_property(
+            window,
+            self.atoms.WM_WINDOW_ROLE,
+            "WM_WINDOW_ROLE",
+            AtomEnum::STRING.into(),
+            u32::MAX,
+        )?;
+        if role.value.is_empty() {
+            return Ok(None);
+        }
+
+        Ok(Some(String::from_utf8_lossy(&role.value).to_string()))
+    }
+
+    // The first type is the preferred one, e.g. "_NET_WM_WINDOW_TYPE_DIALOG" is reported as "dialog"
+    fn get_window_type(&self, window: Window) -> anyhow::Result<Option<String>> {
+        let window_type = self.get_property(
+            window,
+            self.atoms._NET_WM_WINDOW_TYPE,
+            "_NET_WM_WINDOW_TYPE",
+            AtomEnum::ATOM.into(),
+            1,
+        )?;
+        let Some(atom) = window_type.value32().and_then(|mut value| value.next()) else {
+            return Ok(None);
+        };
+
+        let name = self
+            .connection
+            .get_atom_name(atom)
+            .with_context(|| "GetAtomNameRequest failed")?
+            .reply()
+            .with_context(|| "GetAtomNameReply failed")?
+            .name;
+        let name = String::from_utf8_lossy(&name);
+
+        Ok(Some(
+            name.strip_prefix("_NET_WM_WINDOW_TYPE_")
+                .unwrap_or(&name)
+                .to_lowercase(),
+        ))
     }
 
     fn get_property(
@@ -112,22 +306,11 @@ impl X11Client {
             .with_context(|| format!("GetPropertyReply[{property_name}] failed"))
     }
 
-    fn intern_atom(&self, name: &str) -> anyhow::Result<Atom> {
-        Ok(self
-            .connection
-            .intern_atom(false, name.as_bytes())
-            .with_context(|| format!("InternAtomRequest[{name}] failed"))?
-            .reply()
-            .with_context(|| format!("InternAtomReply[{name}] failed"))?
-            .atom)
-    }
-
     fn find_active_window(&self) -> anyhow::Result<Window> {
         let window: Atom = AtomEnum::WINDOW.into();
-        let net_active_window = self.intern_atom("_NET_ACTIVE_WINDOW")?;
         let active_window = self.get_property(
             self.screen_root,
-            net_active_window,
+            self.atoms._NET_ACTIVE_WINDOW,
             "_NET_ACTIVE_WINDOW",
             window,
             1,
@@ -1,5 +1,4 @@
 use async_trait::async_trait;
-use chrono::Utc;
 
 use super::{idle, x11_connection::X11Client, Watcher};
 use crate::report_client::ReportClient;
@@ -26,15 +25,22 @@ impl Watcher for IdleWatcher {
 
         Ok(IdleWatcher {
             client,
-            idle_state: idle::Tracker::new(Utc::now(), report_client.config.idle_timeout),
+            idle_state: idle::Tracker::new(
+                report_client.clock.now(),
+                report_client.config.idle_timeout,
+            ),
         })
     }
 
     async fn run_iteration(&mut self, client: &Arc<ReportClient>) -> anyhow::Result<()> {
         let seconds = self.seconds_since_input().await?;
+        self.idle_state.set_window_timeout(client.idle_timeout());
 
         client
-            .handle_idle_status(self.idle_state.get_with_last_input(Utc::now(), seconds)?)
+            .handle_idle_status(
+                self.idle_state
+                    .get_with_last_input(client.clock.now(), seconds)?,
+            )
             .await
     }
 }
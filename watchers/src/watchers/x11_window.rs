@@ -1,4 +1,7 @@
-use super::{x11_connection::X11Client, Watcher};
+use super::{
+    x11_connection::{WindowData, X11Client},
+    Watcher,
+};
 use crate::report_client::ReportClient;
 use anyhow::Context;
 use async_trait::async_trait;
@@ -7,50 +10,41 @@ use std::sync::Arc;
 
 pub struct WindowWatcher {
     client: X11Client,
-    last_app_id: String,
-    last_title: String,
-    last_wm_instance: String,
+    last_data: Option<WindowData>,
+    is_event_driven: bool,
 }
 
-impl WindowWatcher {
-    pub async fn send_active_window_with_instance(
-        &self,
-        client: &ReportClient,
-        app_id: &str,
-        title: &str,
-        wm_instance: &str,
-    ) -> anyhow::Result<()> {
-        let mut extra_data = HashMap::new();
-        extra_data.insert("wm_instance".to_string(), wm_instance.to_string());
-        client
-            .send_active_window_with_extra(app_id, title, Some(extra_data))
-            .await
+fn extra_data(data: &WindowData) -> HashMap<String, String> {
+    let mut extra_data = HashMap::new();
+    extra_data.insert("wm_instance".to_string(), data.wm_instance.clone());
+    if let Some(pid) = data.pid {
+        extra_data.insert("pid".to_string(), pid.to_string());
+    }
+    if let Some(role) = &data.role {
+        extra_data.insert("role".to_string(), role.clone());
     }
+    if let Some(window_type) = &data.window_type {
+        extra_data.insert("window_type".to_string(), window_type.clone());
+    }
+    extra_data
+}
 
+impl WindowWatcher {
     async fn send_active_window(&mut self, client: &ReportClient) -> anyhow::Result<()> {
         let data = self.client.active_window_data()?;
 
-        if data.app_id != self.last_app_id
-            || data.title != self.last_title
-            || data.wm_instance != self.last_wm_instance
-        {
+        if self.last_data.as_ref() != Some(&data) {
             debug!(
-                r#"Changed window app_id="{}", title="{}", wm_instance="{}""#,
-                data.app_id, data.title, data.wm_instance
+                r#"Changed window app_id="{}", title="{}", wm_instance="{}", pid={:?}, role={:?}, type={:?}"#,
+                data.app_id, data.title, data.wm_instance, data.pid, data.role, data.window_type
             );
-            self.last_app_id = data.app_id.clone();
-            self.last_title = data.title.clone();
-            self.last_wm_instance = data.wm_instance.clone();
+            self.last_data = Some(data.clone());
         }
 
-        self.send_active_window_with_instance(
-            client,
-            &self.last_app_id,
-            &self.last_title,
-            &self.last_wm_instance,
-        )
-        .await
-        .with_context(|| "Failed to send heartbeat for active window")
+        client
+            .send_active_window_with_extra(&data.app_id, &data.title, Some(extra_data(&data)))
+            .await
+            .with_context(|| "Failed to send heartbeat for active window")
     }
 }
 
@@ -58,17 +52,33 @@ impl WindowWatcher {
 impl Watcher for WindowWatcher {
     async fn new(_: &Arc<ReportClient>) -> anyhow::Result<Self> {
         let mut client = X11Client::new()?;
+        // Without _NET_ACTIVE_WINDOW the window is polled
+        let is_event_driven = client.watch_active_window()?;
+        if !is_event_driven {
+            warn!("_NET_ACTIVE_WINDOW is not maintained by the window manager, polling the input focus");
+        }
         client.active_window_data()?;
 
         Ok(WindowWatcher {
             client,
-            last_title: String::new(),
-            last_app_id: String::new(),
-            last_wm_instance: String::new(),
+            last_data: None,
+            is_event_driven,
         })
     }
 
     async fn run_iteration(&mut self, client: &Arc<ReportClient>) -> anyhow::Result<()> {
         self.send_active_window(client).await
     }
+
+    async fn wait_for_event(&mut self) -> anyhow::Result<()> {
+        if self.is_event_driven {
+            self.client.wait_for_change().await
+        } else {
+            std::future::pending().await
+        }
+    }
+
+    fn is_event_driven(&self) -> bool {
+        self.is_event_driven
+    }
 }